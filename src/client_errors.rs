@@ -51,3 +51,25 @@ pub enum MutationError {
     /// Request timed-out waiting for response.
     Timeout,
 }
+
+#[derive(Debug, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, RustcEncodable, RustcDecodable)]
+/// Errors in MPID messaging operations involving Core and Vaults
+pub enum MessagingError {
+    /// SAFE Account does not exist for client
+    NoSuchAccount,
+    /// Storing the header would exceed the recipient's inbox limit
+    InboxFull,
+    /// Storing the message would exceed the sender's outbox limit
+    OutboxFull,
+    /// Requested message or header not found
+    NoSuchMessage,
+    /// Message or header failed signature verification
+    InvalidSignature,
+    /// The recipient has blocked messages from the sender
+    SenderBlocked,
+    /// Request is invalid for its source, e.g. a message whose sender isn't the requester
+    InvalidRequest,
+    /// Unknown error - Errors occuring at Vault level which have no bearing on clients, eg.
+    /// misc errors like serialisation failure, db failure etc
+    Unknown,
+}
@@ -42,6 +42,8 @@
 #![cfg_attr(feature="clippy", deny(clippy, clippy_pedantic))]
 #![cfg_attr(feature="clippy", allow(use_debug))]
 
+extern crate bincode;
+extern crate flate2;
 extern crate rand;
 extern crate xor_name;
 extern crate sodiumoxide;
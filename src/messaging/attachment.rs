@@ -0,0 +1,242 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::fmt::{self, Debug, Formatter};
+
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::secretbox;
+use super::{Error, MAX_BODY_SIZE};
+use xor_name::XorName;
+
+/// Version of the [attachment body](struct.AttachmentRef.html#method.encode_body) encoding,
+/// written as its first byte.
+pub const ATTACHMENTS_VERSION: u8 = 1;
+
+/// A symmetric key with which the content of an attachment is encrypted before being stored on
+/// the network.
+#[derive(PartialEq, Eq, Clone, RustcDecodable, RustcEncodable)]
+pub struct AttachmentKey {
+    key: secretbox::Key,
+}
+
+impl AttachmentKey {
+    /// Generates a random key.
+    pub fn generate() -> AttachmentKey {
+        AttachmentKey { key: secretbox::gen_key() }
+    }
+
+    /// Encrypts `plaintext` under a freshly generated nonce, returning the content to be stored:
+    /// the nonce followed by the ciphertext.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = secretbox::gen_nonce();
+        let mut content = nonce.0.to_vec();
+        content.extend_from_slice(&secretbox::seal(plaintext, &nonce, &self.key));
+        content
+    }
+
+    /// Decrypts `content` as produced by [`encrypt()`](#method.encrypt).
+    pub fn decrypt(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        if content.len() < secretbox::NONCEBYTES {
+            return Err(Error::DecryptionFailed);
+        }
+        let nonce = match secretbox::Nonce::from_slice(&content[..secretbox::NONCEBYTES]) {
+            Some(nonce) => nonce,
+            None => return Err(Error::DecryptionFailed),
+        };
+        secretbox::open(&content[secretbox::NONCEBYTES..], &nonce, &self.key)
+            .map_err(|()| Error::DecryptionFailed)
+    }
+}
+
+impl Debug for AttachmentKey {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter, "AttachmentKey {{ key: <redacted> }}")
+    }
+}
+
+/// A reference to immutable data stored elsewhere on the network, to be sent in a message body in
+/// place of the data itself.
+///
+/// A list of references is encoded as a message body via [`encode_body()`](#method.encode_body),
+/// so it is covered by the sender's signature of the message.  Having fetched the data by name,
+/// the recipient checks it against the reference's size and hash via
+/// [`open()`](#method.open).
+///
+/// Where a reference holds a decryption key, anyone able to read the message body can decrypt the
+/// attachment, so such references should be sent in
+/// [encrypted messages](struct.MpidMessage.html#method.new_encrypted).
+#[derive(PartialEq, Eq, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct AttachmentRef {
+    name: XorName,
+    size: u64,
+    content_hash: sha256::Digest,
+    key: Option<AttachmentKey>,
+}
+
+impl AttachmentRef {
+    /// Constructs a reference to the unencrypted `content` stored under `name`.
+    pub fn new(name: XorName, content: &[u8]) -> AttachmentRef {
+        AttachmentRef {
+            name: name,
+            size: content.len() as u64,
+            content_hash: sha256::hash(content),
+            key: None,
+        }
+    }
+
+    /// Constructs a reference to the `content` stored under `name`, which was produced by
+    /// [`key.encrypt()`](struct.AttachmentKey.html#method.encrypt).
+    pub fn with_key(name: XorName, content: &[u8], key: AttachmentKey) -> AttachmentRef {
+        AttachmentRef { key: Some(key), ..AttachmentRef::new(name, content) }
+    }
+
+    /// The name under which the content is stored.
+    pub fn name(&self) -> &XorName {
+        &self.name
+    }
+
+    /// The length of the stored content.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The SHA256 hash of the stored content.
+    pub fn content_hash(&self) -> &sha256::Digest {
+        &self.content_hash
+    }
+
+    /// The key with which the stored content is encrypted, if any.
+    pub fn key(&self) -> Option<&AttachmentKey> {
+        self.key.as_ref()
+    }
+
+    /// Returns whether `content` matches the reference's size and hash.
+    pub fn verify_content(&self, content: &[u8]) -> bool {
+        content.len() as u64 == self.size && sha256::hash(content) == self.content_hash
+    }
+
+    /// Verifies fetched `content` against the reference, then decrypts it if the reference holds a
+    /// key.
+    ///
+    /// An error will be returned if `content` doesn't match the reference, or if decryption fails.
+    pub fn open(&self, content: &[u8]) -> Result<Vec<u8>, Error> {
+        if !self.verify_content(content) {
+            return Err(Error::Malformed);
+        }
+        match self.key {
+            Some(ref key) => key.decrypt(content),
+            None => Ok(content.to_vec()),
+        }
+    }
+
+    /// Encodes `attachments` as a message body: the version byte followed by the serialised list.
+    ///
+    /// An error will be returned if the encoded body exceeds
+    /// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
+    pub fn encode_body(attachments: &[AttachmentRef]) -> Result<Vec<u8>, Error> {
+        super::serialise_versioned(ATTACHMENTS_VERSION, &attachments, MAX_BODY_SIZE)
+    }
+
+    /// Decodes a message body produced by [`encode_body()`](#method.encode_body).
+    ///
+    /// An error will be returned if `body` exceeds `MAX_BODY_SIZE`, has an unknown version or
+    /// isn't exactly an encoded list.
+    pub fn decode_body(body: &[u8]) -> Result<Vec<AttachmentRef>, Error> {
+        super::deserialise_versioned(ATTACHMENTS_VERSION, body, MAX_BODY_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{self, Error, MAX_BODY_SIZE, MpidMessage};
+
+    #[test]
+    fn full() {
+        let plain_content = messaging::generate_random_bytes(1000);
+        let plain = AttachmentRef::new(rand::random(), &plain_content);
+        assert_eq!(plain.size(), 1000);
+        assert!(plain.key().is_none());
+        assert!(plain.verify_content(&plain_content));
+        assert_eq!(unwrap_result!(plain.open(&plain_content)), plain_content);
+
+        let key = AttachmentKey::generate();
+        let secret = messaging::generate_random_bytes(1000);
+        let encrypted_content = key.encrypt(&secret);
+        let encrypted = AttachmentRef::with_key(rand::random(), &encrypted_content, key.clone());
+        assert_eq!(encrypted.key(), Some(&key));
+        assert_eq!(unwrap_result!(encrypted.open(&encrypted_content)), secret);
+        match AttachmentKey::generate().decrypt(&encrypted_content) {
+            Err(Error::DecryptionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match key.decrypt(&encrypted_content[..10]) {
+            Err(Error::DecryptionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Each encryption uses a fresh nonce, and the key isn't exposed by `Debug`.
+        assert!(key.encrypt(&secret) != encrypted_content);
+        assert_eq!(unwrap_result!(key.decrypt(&key.encrypt(&secret))), secret);
+        assert_eq!(format!("{:?}", key), "AttachmentKey { key: <redacted> }");
+
+        // Tampered or truncated content is rejected.
+        let mut tampered = plain_content.clone();
+        tampered[0] ^= 1;
+        assert!(!plain.verify_content(&tampered));
+        assert!(!plain.verify_content(&plain_content[1..]));
+        match encrypted.open(&tampered) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // References round-trip via a signed message body.
+        let attachments = vec![plain, encrypted];
+        let body = unwrap_result!(AttachmentRef::encode_body(&attachments));
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      rand::random(),
+                                                      body.clone(),
+                                                      &secret_key));
+        assert!(message.verify(&public_key));
+        let body = unwrap_result!(message.body());
+        assert_eq!(unwrap_result!(AttachmentRef::decode_body(&body)), attachments);
+
+        // Malformed bodies are rejected.
+        for malformed in &[vec![],
+                           vec![ATTACHMENTS_VERSION + 1],
+                           body[..body.len() - 1].to_vec(),
+                           vec![ATTACHMENTS_VERSION, 255, 255, 255, 255, 255, 255, 255, 255]] {
+            assert!(AttachmentRef::decode_body(malformed).is_err());
+        }
+        let mut trailing = body;
+        trailing.push(0);
+        match AttachmentRef::decode_body(&trailing) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match AttachmentRef::decode_body(&vec![ATTACHMENTS_VERSION; MAX_BODY_SIZE + 1]) {
+            Err(Error::InputTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,546 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use messaging;
+use rand;
+use sodiumoxide::crypto::box_;
+use super::{Error, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE, MAX_ENCRYPTED_METADATA_SIZE,
+            MpidMessage, MpidPublicId};
+use super::{mpid_header, mpid_message};
+use super::signing::{Signer, Verifier};
+use xor_name::XorName;
+
+/// Version of the [`ChunkManifest`](struct.ChunkManifest.html) encoding, written as the first
+/// byte of a manifest message's body.
+pub const CHUNK_MANIFEST_VERSION: u8 = 1;
+/// Maximum number of parts into which a [chunked message](struct.ChunkedMessage.html)'s payload
+/// can be split (1,024).
+pub const MAX_CHUNK_COUNT: usize = 1024;
+/// Maximum allowed length of a [chunked message](struct.ChunkedMessage.html)'s payload (about 99
+/// MiB).
+pub const MAX_CHUNKED_PAYLOAD_SIZE: usize = MAX_CHUNK_COUNT * MAX_BODY_SIZE;
+/// Maximum allowed length of an
+/// [encrypted chunked message](struct.ChunkedMessage.html#method.new_encrypted)'s payload, leaving
+/// room for the encryption overhead of each part.
+pub const MAX_ENCRYPTED_CHUNKED_PAYLOAD_SIZE: usize = MAX_CHUNK_COUNT * MAX_ENCRYPTED_BODY_SIZE;
+
+/// The body of the manifest message of a [`ChunkedMessage`](struct.ChunkedMessage.html), listing
+/// the names of the part messages which together hold the payload.
+///
+/// The body is the version byte followed by the serialised manifest.  Since the name of a message
+/// commits to its header, and the header to the message's recipient and body, the sender's
+/// signature of the manifest message covers every part.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct ChunkManifest {
+    payload_size: u64,
+    parts: Vec<XorName>,
+}
+
+impl ChunkManifest {
+    /// Parses the body of the unencrypted `manifest`, checking that the listed parts are
+    /// consistent with the payload size.  The manifest message itself isn't verified.
+    pub fn from_message(manifest: &MpidMessage) -> Result<ChunkManifest, Error> {
+        if manifest.is_encrypted() {
+            return Err(Error::Malformed);
+        }
+        Self::from_body(&try!(manifest.body()), false)
+    }
+
+    /// As per [`from_message()`](#method.from_message), except that the encrypted `manifest` is
+    /// first decrypted using the recipient's encryption key pair.
+    pub fn open_message(manifest: &MpidMessage,
+                        public_key: &box_::PublicKey,
+                        secret_key: &box_::SecretKey)
+                        -> Result<ChunkManifest, Error> {
+        Self::from_body(&try!(manifest.open(public_key, secret_key)), true)
+    }
+
+    /// The length of the payload.
+    pub fn payload_size(&self) -> u64 {
+        self.payload_size
+    }
+
+    /// The names of the part messages, in payload order.
+    pub fn parts(&self) -> &[XorName] {
+        &self.parts
+    }
+
+    /// The names of the parts which aren't in `held`, in payload order.
+    pub fn missing_parts(&self, held: &[XorName]) -> Vec<XorName> {
+        self.parts.iter().filter(|name| !held.contains(name)).cloned().collect()
+    }
+
+    fn from_body(body: &[u8], encrypted: bool) -> Result<ChunkManifest, Error> {
+        let chunk_manifest: ChunkManifest =
+            try!(super::deserialise_versioned(CHUNK_MANIFEST_VERSION, body, MAX_BODY_SIZE));
+        let part_size = max_part_size(encrypted);
+        if chunk_manifest.payload_size > (MAX_CHUNK_COUNT * part_size) as u64 ||
+           chunk_manifest.parts.len() !=
+           chunk_count(chunk_manifest.payload_size as usize, part_size) {
+            return Err(Error::Malformed);
+        }
+        Ok(chunk_manifest)
+    }
+}
+
+/// A payload too large for a single [`MpidMessage`](struct.MpidMessage.html), split into several
+/// part messages plus a manifest message listing them.
+///
+/// The manifest message carries the caller's metadata and acts as the logical header of the
+/// payload; each part message has empty metadata and holds up to
+/// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html) bytes of the payload.  The signed header of
+/// each part names the manifest's GUID as its
+/// [`chunk_of()`](struct.MpidHeader.html#method.chunk_of), so that recipients can group the parts
+/// with their manifest.  All of them are sent to the recipient as normal messages, and count
+/// towards the sender's outbox.  The recipient passes the manifest and the retrieved parts to
+/// [`reassemble()`](#method.reassemble), or to [`open()`](#method.open) if they're encrypted.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct ChunkedMessage {
+    manifest: MpidMessage,
+    parts: Vec<MpidMessage>,
+}
+
+impl ChunkedMessage {
+    /// Splits `payload` into part messages and signs a manifest message listing them.  For details
+    /// on the other arguments, see [MpidMessage::new()](struct.MpidMessage.html#method.new).
+    ///
+    /// An error will be returned if `payload` exceeds
+    /// [`MAX_CHUNKED_PAYLOAD_SIZE`](constant.MAX_CHUNKED_PAYLOAD_SIZE.html), or if constructing
+    /// any of the messages fails.
+    pub fn new<S: Signer + ?Sized>(sender: XorName,
+                                   metadata: Vec<u8>,
+                                   recipient: XorName,
+                                   payload: &[u8],
+                                   signer: &S)
+                                   -> Result<ChunkedMessage, Error> {
+        Self::split(sender, metadata, recipient, None, payload, signer)
+    }
+
+    /// As per [`new()`](#method.new), except that the metadata and the bodies of the manifest and
+    /// every part are encrypted to `recipient`, as per
+    /// [MpidMessage::new_encrypted()](struct.MpidMessage.html#method.new_encrypted).  Each part
+    /// holds up to [`MAX_ENCRYPTED_BODY_SIZE`](constant.MAX_ENCRYPTED_BODY_SIZE.html) bytes of the
+    /// payload, which mustn't exceed
+    /// [`MAX_ENCRYPTED_CHUNKED_PAYLOAD_SIZE`](constant.MAX_ENCRYPTED_CHUNKED_PAYLOAD_SIZE.html).
+    pub fn new_encrypted<S: Signer + ?Sized>(sender: XorName,
+                                             metadata: Vec<u8>,
+                                             recipient: &MpidPublicId,
+                                             payload: &[u8],
+                                             signer: &S)
+                                             -> Result<ChunkedMessage, Error> {
+        if metadata.len() > MAX_ENCRYPTED_METADATA_SIZE {
+            return Err(Error::MetadataTooLarge);
+        }
+        Self::split(sender,
+                    metadata,
+                    recipient.name(),
+                    Some(recipient.encryption_key()),
+                    payload,
+                    signer)
+    }
+
+    /// The manifest message.
+    pub fn manifest(&self) -> &MpidMessage {
+        &self.manifest
+    }
+
+    /// The part messages, in payload order.
+    pub fn parts(&self) -> &[MpidMessage] {
+        &self.parts
+    }
+
+    /// Consumes the chunked message, returning the part messages followed by the manifest message.
+    /// Sending them in this order means all parts are available once the manifest arrives.
+    pub fn into_messages(self) -> Vec<MpidMessage> {
+        let mut messages = self.parts;
+        messages.push(self.manifest);
+        messages
+    }
+
+    /// Verifies `manifest` and the listed messages among `parts` via `verifier`, e.g. the sender's
+    /// `PublicKey`, and returns the reassembled payload.  `parts` may be in any order, and messages
+    /// not listed by the manifest are ignored.
+    ///
+    /// An error will be returned if any message fails validation or verification, if any message
+    /// is encrypted, if any part is addressed differently from the manifest or doesn't name it as
+    /// its `chunk_of()`, or if any listed part is missing, in which case `Error::MissingParts`
+    /// lists them.
+    pub fn reassemble<V: Verifier + ?Sized>(manifest: &MpidMessage,
+                                            parts: &[MpidMessage],
+                                            verifier: &V)
+                                            -> Result<Vec<u8>, Error> {
+        try!(Self::verify_message(manifest, verifier));
+        let chunk_manifest = try!(ChunkManifest::from_message(manifest));
+        Self::join(manifest,
+                   &chunk_manifest,
+                   parts,
+                   verifier,
+                   |part| part.body())
+    }
+
+    /// As per [`reassemble()`](#method.reassemble), except that the manifest and parts must be
+    /// encrypted, and are decrypted using the recipient's encryption key pair.
+    pub fn open<V: Verifier + ?Sized>(manifest: &MpidMessage,
+                                      parts: &[MpidMessage],
+                                      verifier: &V,
+                                      public_key: &box_::PublicKey,
+                                      secret_key: &box_::SecretKey)
+                                      -> Result<Vec<u8>, Error> {
+        try!(Self::verify_message(manifest, verifier));
+        let chunk_manifest = try!(ChunkManifest::open_message(manifest, public_key, secret_key));
+        Self::join(manifest,
+                   &chunk_manifest,
+                   parts,
+                   verifier,
+                   |part| part.open(public_key, secret_key))
+    }
+
+    fn split<S: Signer + ?Sized>(sender: XorName,
+                                 metadata: Vec<u8>,
+                                 recipient: XorName,
+                                 encryption_key: Option<&box_::PublicKey>,
+                                 payload: &[u8],
+                                 signer: &S)
+                                 -> Result<ChunkedMessage, Error> {
+        let part_size = max_part_size(encryption_key.is_some());
+        if payload.len() > MAX_CHUNK_COUNT * part_size {
+            return Err(Error::BodyTooLarge);
+        }
+        let mut rng = rand::thread_rng();
+        let manifest_guid = mpid_header::random_guid(&mut rng);
+        let created = messaging::seconds_since_epoch();
+        let mut parts = Vec::with_capacity(chunk_count(payload.len(), part_size));
+        let mut part_names = Vec::with_capacity(parts.capacity());
+        for chunk in payload.chunks(part_size) {
+            let mut detail = mpid_header::Detail::new(sender.clone(),
+                                                      vec![],
+                                                      mpid_header::random_guid(&mut rng),
+                                                      created);
+            detail.chunk_of = Some(manifest_guid);
+            let part = try!(new_message(detail,
+                                        recipient.clone(),
+                                        chunk.to_vec(),
+                                        encryption_key,
+                                        signer));
+            part_names.push(try!(part.name()));
+            parts.push(part);
+        }
+        let chunk_manifest = ChunkManifest {
+            payload_size: payload.len() as u64,
+            parts: part_names,
+        };
+        let mut detail = mpid_header::Detail::new(sender, metadata, manifest_guid, created);
+        if let Some(encryption_key) = encryption_key {
+            detail.metadata = mpid_header::seal_metadata(&detail.metadata, encryption_key);
+            detail.encrypted_metadata = true;
+        }
+        let manifest = try!(new_message(detail,
+                                        recipient,
+                                        try!(super::serialise_versioned(CHUNK_MANIFEST_VERSION,
+                                                                        &chunk_manifest,
+                                                                        MAX_BODY_SIZE)),
+                                        encryption_key,
+                                        signer));
+        Ok(ChunkedMessage {
+            manifest: manifest,
+            parts: parts,
+        })
+    }
+
+    fn verify_message<V: Verifier + ?Sized>(message: &MpidMessage,
+                                            verifier: &V)
+                                            -> Result<(), Error> {
+        try!(message.validate());
+        if !message.verify(verifier) {
+            return Err(Error::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    // Verifies the parts listed by `chunk_manifest` and concatenates their plaintext bodies, as
+    // returned by `body_of`.
+    fn join<V, F>(manifest: &MpidMessage,
+                  chunk_manifest: &ChunkManifest,
+                  parts: &[MpidMessage],
+                  verifier: &V,
+                  body_of: F)
+                  -> Result<Vec<u8>, Error>
+        where V: Verifier + ?Sized,
+              F: Fn(&MpidMessage) -> Result<Vec<u8>, Error>
+    {
+        let mut held = HashMap::new();
+        for part in parts {
+            let _ = held.insert(try!(part.name()), part);
+        }
+        let missing = chunk_manifest.parts
+                                    .iter()
+                                    .filter(|name| !held.contains_key(name))
+                                    .cloned()
+                                    .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(Error::MissingParts(missing));
+        }
+
+        let mut payload = Vec::with_capacity(chunk_manifest.payload_size as usize);
+        for name in &chunk_manifest.parts {
+            let part = match held.get(name) {
+                Some(part) => part,
+                None => return Err(Error::MissingParts(vec![name.clone()])),
+            };
+            try!(part.validate());
+            if part.header().sender() != manifest.header().sender() ||
+               part.recipient() != manifest.recipient() ||
+               part.header().chunk_of() != Some(manifest.header().guid()) ||
+               part.is_encrypted() != manifest.is_encrypted() {
+                return Err(Error::Malformed);
+            }
+            if !part.verify(verifier) {
+                return Err(Error::InvalidSignature);
+            }
+            payload.extend_from_slice(&try!(body_of(part)));
+        }
+        if payload.len() as u64 != chunk_manifest.payload_size {
+            return Err(Error::Malformed);
+        }
+        Ok(payload)
+    }
+}
+
+// Constructs a message from `detail`, encrypting `body` to `encryption_key` if given.
+fn new_message<S: Signer + ?Sized>(mut detail: mpid_header::Detail,
+                                   recipient: XorName,
+                                   body: Vec<u8>,
+                                   encryption_key: Option<&box_::PublicKey>,
+                                   signer: &S)
+                                   -> Result<MpidMessage, Error> {
+    let body = match encryption_key {
+        Some(encryption_key) => {
+            detail.encrypted_body = true;
+            mpid_message::seal_body(&body, encryption_key)
+        }
+        None => body,
+    };
+    mpid_message::new_message(detail, recipient, body, signer)
+}
+
+// The maximum length of the payload held by each part.
+fn max_part_size(encrypted: bool) -> usize {
+    if encrypted {
+        MAX_ENCRYPTED_BODY_SIZE
+    } else {
+        MAX_BODY_SIZE
+    }
+}
+
+// The number of parts needed to hold a payload of `payload_size` bytes.
+fn chunk_count(payload_size: usize, part_size: usize) -> usize {
+    (payload_size + part_size - 1) / part_size
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::{box_, sign};
+    use xor_name::XorName;
+    use messaging::{self, Error, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE, MpidMessage,
+                    MpidPublicId};
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let payload = messaging::generate_random_bytes(2 * MAX_BODY_SIZE + 10);
+
+        let chunked = unwrap_result!(ChunkedMessage::new(sender.clone(),
+                                                         vec![1, 2, 3],
+                                                         recipient.clone(),
+                                                         &payload,
+                                                         &secret_key));
+        assert_eq!(chunked.parts().len(), 3);
+        assert_eq!(unwrap_result!(chunked.manifest().header().metadata()), vec![1, 2, 3]);
+        assert!(chunked.manifest().header().chunk_of().is_none());
+        for part in chunked.parts() {
+            assert_eq!(part.header().chunk_of(), Some(chunked.manifest().header().guid()));
+        }
+        let chunk_manifest = unwrap_result!(ChunkManifest::from_message(chunked.manifest()));
+        assert_eq!(chunk_manifest.payload_size(), payload.len() as u64);
+        let part_names = chunked.parts()
+                                .iter()
+                                .map(|part| unwrap_result!(part.name()))
+                                .collect::<Vec<_>>();
+        assert_eq!(chunk_manifest.parts(), &part_names[..]);
+        assert_eq!(chunk_manifest.missing_parts(&part_names[1..]),
+                   vec![part_names[0].clone()]);
+
+        // Parts are reassembled in manifest order, ignoring unlisted messages.
+        let manifest = chunked.manifest().clone();
+        let mut parts = chunked.clone().into_messages();
+        assert_eq!(parts.pop(), Some(manifest.clone()));
+        parts.reverse();
+        let other = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    vec![],
+                                                    &secret_key));
+        parts.push(other);
+        assert_eq!(unwrap_result!(ChunkedMessage::reassemble(&manifest, &parts, &public_key)),
+                   payload);
+        let (other_public_key, _) = sign::gen_keypair();
+        match ChunkedMessage::reassemble(&manifest, &parts, &other_public_key) {
+            Err(Error::InvalidSignature) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Missing parts are reported.
+        match ChunkedMessage::reassemble(&manifest, &chunked.parts()[1..], &public_key) {
+            Err(Error::MissingParts(missing)) => assert_eq!(missing, vec![part_names[0].clone()]),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Encrypted messages are rejected.
+        let (encryption_key, _) = box_::gen_keypair();
+        let recipient_id = MpidPublicId::new(public_key, encryption_key);
+        let encrypted = unwrap_result!(ChunkedMessage::new_encrypted(sender.clone(),
+                                                                     vec![],
+                                                                     &recipient_id,
+                                                                     &[1, 2, 3],
+                                                                     &secret_key));
+        match ChunkedMessage::reassemble(encrypted.manifest(), encrypted.parts(), &public_key) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // A substituted part, even if validly signed, isn't accepted in place of a listed one.
+        let mut parts = chunked.parts().to_vec();
+        parts[1] = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                   vec![],
+                                                   recipient.clone(),
+                                                   vec![0; MAX_BODY_SIZE],
+                                                   &secret_key));
+        match ChunkedMessage::reassemble(&manifest, &parts, &public_key) {
+            Err(Error::MissingParts(missing)) => assert_eq!(missing, vec![part_names[1].clone()]),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Empty and oversized payloads.
+        let empty = unwrap_result!(ChunkedMessage::new(sender.clone(),
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       &[],
+                                                       &secret_key));
+        assert!(empty.parts().is_empty());
+        assert!(unwrap_result!(ChunkedMessage::reassemble(empty.manifest(), &[], &public_key))
+                    .is_empty());
+        match ChunkManifest::from_message(&parts[0]) {
+            Err(_) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let mut body = chunked.manifest().raw_body().clone();
+        body[0] = CHUNK_MANIFEST_VERSION + 1;
+        let unknown_version = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                              vec![],
+                                                              recipient.clone(),
+                                                              body,
+                                                              &secret_key));
+        match ChunkManifest::from_message(&unknown_version) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let oversized = vec![0; MAX_CHUNKED_PAYLOAD_SIZE + 1];
+        match ChunkedMessage::new(sender, vec![], recipient, &oversized, &secret_key) {
+            Err(Error::BodyTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn encrypted() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (encryption_key, decryption_key) = box_::gen_keypair();
+        let (recipient_signing_key, _) = sign::gen_keypair();
+        let recipient = MpidPublicId::new(recipient_signing_key, encryption_key);
+        let sender: XorName = rand::random();
+        let payload = messaging::generate_random_bytes(MAX_ENCRYPTED_BODY_SIZE + 10);
+
+        let chunked = unwrap_result!(ChunkedMessage::new_encrypted(sender.clone(),
+                                                                   b"Subject".to_vec(),
+                                                                   &recipient,
+                                                                   &payload,
+                                                                   &secret_key));
+        assert_eq!(chunked.parts().len(), 2);
+        assert!(chunked.manifest().is_encrypted());
+        assert!(chunked.manifest().header().is_metadata_encrypted());
+        assert_eq!(unwrap_result!(chunked.manifest()
+                                         .header()
+                                         .open_metadata(&encryption_key, &decryption_key)),
+                   b"Subject".to_vec());
+        for part in chunked.parts() {
+            assert!(part.is_encrypted());
+            assert_eq!(*part.recipient(), recipient.name());
+            assert_eq!(part.header().chunk_of(), Some(chunked.manifest().header().guid()));
+        }
+        match ChunkManifest::from_message(chunked.manifest()) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let chunk_manifest = unwrap_result!(ChunkManifest::open_message(chunked.manifest(),
+                                                                        &encryption_key,
+                                                                        &decryption_key));
+        assert_eq!(chunk_manifest.payload_size(), payload.len() as u64);
+        assert_eq!(unwrap_result!(ChunkedMessage::open(chunked.manifest(),
+                                                       chunked.parts(),
+                                                       &public_key,
+                                                       &encryption_key,
+                                                       &decryption_key)),
+                   payload);
+
+        // Only the recipient can open it.
+        let (other_encryption_key, other_decryption_key) = box_::gen_keypair();
+        assert!(ChunkedMessage::open(chunked.manifest(),
+                                     chunked.parts(),
+                                     &public_key,
+                                     &other_encryption_key,
+                                     &other_decryption_key)
+                    .is_err());
+
+        // Parts of another chunked message aren't accepted, even if listed names matched.
+        let other = unwrap_result!(ChunkedMessage::new_encrypted(sender.clone(),
+                                                                 vec![],
+                                                                 &recipient,
+                                                                 &payload,
+                                                                 &secret_key));
+        match ChunkedMessage::open(chunked.manifest(),
+                                   other.parts(),
+                                   &public_key,
+                                   &encryption_key,
+                                   &decryption_key) {
+            Err(Error::MissingParts(_)) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Oversized payloads.
+        let oversized = vec![0; MAX_ENCRYPTED_CHUNKED_PAYLOAD_SIZE + 1];
+        match ChunkedMessage::new_encrypted(sender, vec![], &recipient, &oversized, &secret_key) {
+            Err(Error::BodyTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
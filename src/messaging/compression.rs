@@ -0,0 +1,142 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use super::{Error, MAX_BODY_SIZE};
+
+/// Maximum allowed length of a [compressed](enum.Codec.html) message body once decompressed
+/// (407,040 bytes, i.e. four times [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html)).
+pub const MAX_DECOMPRESSED_BODY_SIZE: usize = 4 * MAX_BODY_SIZE;
+/// The codecs supported by this library, in order of preference.
+pub const SUPPORTED_CODECS: &'static [Codec] = &[Codec::Deflate, Codec::None];
+
+/// The compression applied to a message body, as carried in the signed header.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub enum Codec {
+    /// The body isn't compressed.
+    None,
+    /// The body is compressed using raw DEFLATE (RFC 1951).
+    Deflate,
+}
+
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::None
+    }
+}
+
+impl Codec {
+    /// Returns the first of `preferred` which is also in `supported_by_peer`, or `Codec::None` if
+    /// there are none in common.
+    pub fn negotiate(preferred: &[Codec], supported_by_peer: &[Codec]) -> Codec {
+        preferred.iter()
+                 .find(|codec| supported_by_peer.contains(codec))
+                 .cloned()
+                 .unwrap_or(Codec::None)
+    }
+
+    /// Compresses `data`.
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+                try!(encoder.write_all(data));
+                Ok(try!(encoder.finish()))
+            }
+        }
+    }
+
+    /// Decompresses `data`, which must not expand to more than `limit` bytes.  Decompression stops
+    /// as soon as the limit is exceeded, so malicious input can't consume more memory than this.
+    ///
+    /// An error will be returned if `data` isn't valid for the codec or exceeds `limit` bytes once
+    /// decompressed.
+    pub fn decompress(self, data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+        let decompressed = match self {
+            Codec::None => data.to_vec(),
+            Codec::Deflate => {
+                let mut decompressed = Vec::new();
+                let _ = try!(DeflateDecoder::new(data)
+                                 .take(limit as u64 + 1)
+                                 .read_to_end(&mut decompressed)
+                                 .map_err(|_| Error::DecompressionFailed));
+                decompressed
+            }
+        };
+        if decompressed.len() > limit {
+            return Err(Error::DecompressionFailed);
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use messaging::{self, Error, MAX_BODY_SIZE};
+
+    #[test]
+    fn full() {
+        assert_eq!(Codec::negotiate(SUPPORTED_CODECS, &[Codec::None, Codec::Deflate]),
+                   Codec::Deflate);
+        assert_eq!(Codec::negotiate(SUPPORTED_CODECS, &[Codec::None]), Codec::None);
+        assert_eq!(Codec::negotiate(SUPPORTED_CODECS, &[]), Codec::None);
+
+        let text = b"All work and no play makes Jack a dull boy. ".iter()
+                                                                     .cycle()
+                                                                     .take(10000)
+                                                                     .cloned()
+                                                                     .collect::<Vec<u8>>();
+        let random = messaging::generate_random_bytes(1000);
+        for data in &[vec![], text.clone(), random] {
+            for codec in SUPPORTED_CODECS {
+                let compressed = unwrap_result!(codec.compress(data));
+                assert_eq!(unwrap_result!(codec.decompress(&compressed, data.len())), *data);
+            }
+        }
+        let compressed = unwrap_result!(Codec::Deflate.compress(&text));
+        assert!(compressed.len() < text.len() / 10);
+
+        // Output beyond the limit, and invalid input, is rejected.
+        match Codec::Deflate.decompress(&compressed, text.len() - 1) {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match Codec::None.decompress(&text, text.len() - 1) {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match Codec::Deflate.decompress(&[0xff; 100], 1000) {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // A body-sized input expanding far beyond the limit is cut off at the limit.
+        let zeros = vec![0; 100 * MAX_DECOMPRESSED_BODY_SIZE];
+        let bomb = unwrap_result!(Codec::Deflate.compress(&zeros));
+        assert!(bomb.len() <= MAX_BODY_SIZE);
+        match Codec::Deflate.decompress(&bomb, MAX_DECOMPRESSED_BODY_SIZE) {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
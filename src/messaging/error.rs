@@ -15,7 +15,12 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+use std::io;
+
+use client_errors::MessagingError;
 use maidsafe_utilities::serialisation::SerialisationError;
+use super::ValidationError;
+use xor_name::XorName;
 
 /// Error types relating to MPID messaging.
 #[derive(Debug)]
@@ -26,8 +31,49 @@ pub enum Error {
     /// Used where the length of a [message's `body`](struct.MpidMessage.html#method.new) exceeds
     /// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
     BodyTooLarge,
+    /// Used where a header or message fails signature verification.
+    InvalidSignature,
+    /// Used where storing a header would exceed the account's
+    /// [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html).
+    InboxFull,
+    /// Used where storing a message would exceed the account's
+    /// [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html).
+    OutboxFull,
+    /// Used where the requested header or message doesn't exist in the inbox or outbox.
+    NoSuchMessage,
+    /// Used where a request isn't valid for the given source, e.g. a `PutHeader` whose sender
+    /// doesn't match the source of the request.
+    InvalidRequest,
+    /// Used where serialised input exceeds the maximum size for the type being deserialised.
+    InputTooLarge,
+    /// Used where deserialised data violates an invariant of its type, e.g. trailing bytes after
+    /// the encoded value or a message whose header isn't bound to its body.
+    Malformed,
+    /// Used where the recipient has [blocked](struct.MpidManager.html#method.block_sender) the
+    /// sender of a header.
+    SenderBlocked,
+    /// Used where an [`MpidMessageBuilder`](struct.MpidMessageBuilder.html) can't build a message,
+    /// listing every violated constraint.
+    Validation(Vec<ValidationError>),
+    /// Used where a header's creation timestamp violates a
+    /// [`ClockSkewPolicy`](struct.ClockSkewPolicy.html).
+    InvalidTimestamp,
+    /// Used where a header or message is put after its expiry time.
+    Expired,
+    /// Used where the body of a message isn't encrypted or can't be decrypted with the given keys.
+    DecryptionFailed,
+    /// Used where a [chunked message](struct.ChunkedMessage.html) can't be reassembled because the
+    /// named parts are missing.
+    MissingParts(Vec<XorName>),
+    /// Used where a [compressed](enum.Codec.html) body is invalid, or would exceed its size limit
+    /// once decompressed.
+    DecompressionFailed,
+    /// Used where a [`Signer`](trait.Signer.html) fails to produce a signature.
+    SigningFailed,
     /// Serialisation error.
     Serialisation(SerialisationError),
+    /// I/O error, e.g. from a file-backed [`MailboxStore`](trait.MailboxStore.html).
+    Io(io::Error),
 }
 
 impl From<SerialisationError> for Error {
@@ -35,3 +81,35 @@ impl From<SerialisationError> for Error {
         Error::Serialisation(error)
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<Error> for MessagingError {
+    fn from(error: Error) -> MessagingError {
+        match error {
+            Error::InvalidSignature => MessagingError::InvalidSignature,
+            Error::InboxFull => MessagingError::InboxFull,
+            Error::OutboxFull => MessagingError::OutboxFull,
+            Error::NoSuchMessage |
+            Error::MissingParts(_) => MessagingError::NoSuchMessage,
+            Error::SenderBlocked => MessagingError::SenderBlocked,
+            Error::MetadataTooLarge |
+            Error::BodyTooLarge |
+            Error::InputTooLarge |
+            Error::Malformed |
+            Error::Validation(_) |
+            Error::InvalidTimestamp |
+            Error::Expired |
+            Error::DecryptionFailed |
+            Error::DecompressionFailed |
+            Error::InvalidRequest => MessagingError::InvalidRequest,
+            Error::SigningFailed |
+            Error::Serialisation(_) |
+            Error::Io(_) => MessagingError::Unknown,
+        }
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use sodiumoxide::crypto::hash::sha256;
+use super::{Error, MailboxEntry, MailboxStore, MemoryMailboxStore};
+use xor_name::XorName;
+
+const SNAPSHOT_FILE: &'static str = "mailbox.snapshot";
+const SNAPSHOT_TEMP_FILE: &'static str = "mailbox.snapshot.tmp";
+const JOURNAL_FILE: &'static str = "mailbox.journal";
+const LENGTH_SIZE: usize = 4;
+
+#[derive(RustcDecodable, RustcEncodable)]
+enum JournalRecord {
+    Put(XorName, XorName, MailboxEntry),
+    Delete(XorName, XorName),
+}
+
+/// A crash-safe [`MailboxStore`](trait.MailboxStore.html) backed by files in a single directory.
+///
+/// All entries are held in memory.  Every mutation is appended to a write-ahead journal and synced
+/// to disk before being applied, so a store reopened after a crash contains every mutation which
+/// returned successfully.  [`compact()`](#method.compact) folds the journal into a snapshot file.
+///
+/// Each journal record is written as a four-byte little-endian length, the serialised record and
+/// its SHA256 checksum.  A torn or corrupt record at the end of the journal (e.g. from a crash
+/// mid-write) is discarded when the store is opened, while a corrupt record followed by further
+/// records causes opening to fail with `Error::Malformed`, leaving the journal untouched.
+pub struct FileMailboxStore {
+    dir: PathBuf,
+    journal: File,
+    entries: MemoryMailboxStore,
+}
+
+impl FileMailboxStore {
+    /// Opens the store held in `dir`, creating the directory and an empty store if required.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<FileMailboxStore, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(fs::create_dir_all(&dir));
+
+        let mut entries = match File::open(dir.join(SNAPSHOT_FILE)) {
+            Ok(mut file) => {
+                let mut contents = vec![];
+                let _ = try!(file.read_to_end(&mut contents));
+                try!(deserialise(&contents))
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => MemoryMailboxStore::new(),
+            Err(error) => return Err(From::from(error)),
+        };
+
+        // Replaying is idempotent, so records already folded into the snapshot by an interrupted
+        // `compact()` are harmless.
+        let mut journal = try!(OpenOptions::new()
+                                   .read(true)
+                                   .write(true)
+                                   .create(true)
+                                   .truncate(false)
+                                   .open(dir.join(JOURNAL_FILE)));
+        let mut contents = vec![];
+        let _ = try!(journal.read_to_end(&mut contents));
+        let mut offset = 0;
+        while offset < contents.len() {
+            match Self::parse_record(&contents[offset..]) {
+                Ok((record, length)) => {
+                    try!(Self::apply(&mut entries, record));
+                    offset += length;
+                }
+                Err(Some(length)) if offset.checked_add(length)
+                                           .map_or(true, |end| end < contents.len()) => {
+                    return Err(Error::Malformed)
+                }
+                Err(_) => {
+                    try!(journal.set_len(offset as u64));
+                    try!(journal.sync_all());
+                    break;
+                }
+            }
+        }
+
+        let journal = try!(OpenOptions::new().append(true).open(dir.join(JOURNAL_FILE)));
+        Ok(FileMailboxStore {
+            dir: dir,
+            journal: journal,
+            entries: entries,
+        })
+    }
+
+    /// Writes all entries to a new snapshot file and empties the journal.
+    ///
+    /// The snapshot is synced to disk before replacing the old one, and the replacement is synced
+    /// before the journal is emptied, so a crash at any point leaves either the old or the new
+    /// snapshot alongside a journal which completes it.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let temp_path = self.dir.join(SNAPSHOT_TEMP_FILE);
+        {
+            let mut file = try!(File::create(&temp_path));
+            try!(file.write_all(&try!(serialise(&self.entries))));
+            try!(file.sync_all());
+        }
+        try!(fs::rename(&temp_path, self.dir.join(SNAPSHOT_FILE)));
+        try!(Self::sync_dir(&self.dir));
+        try!(self.journal.set_len(0));
+        try!(self.journal.sync_all());
+        Ok(())
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> Result<(), Error> {
+        let serialised = try!(serialise(record));
+        let length = serialised.len();
+        let mut buffer = Vec::with_capacity(LENGTH_SIZE + length + sha256::DIGESTBYTES);
+        for i in 0..LENGTH_SIZE {
+            buffer.push((length >> (8 * i)) as u8);
+        }
+        buffer.extend_from_slice(&serialised);
+        buffer.extend_from_slice(&sha256::hash(&serialised).0);
+        // Truncate any partially written record, so that it can't be followed by further records.
+        let journal_length = try!(self.journal.metadata()).len();
+        match self.journal.write_all(&buffer).and_then(|()| self.journal.sync_data()) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                let _ = self.journal.set_len(journal_length);
+                Err(From::from(error))
+            }
+        }
+    }
+
+    // Syncs `dir` so that a rename within it is durable.  Directories can't be opened as files on
+    // Windows, so this is only done on Unix.
+    #[cfg(unix)]
+    fn sync_dir(dir: &Path) -> Result<(), Error> {
+        try!(try!(File::open(dir)).sync_all());
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn sync_dir(_dir: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    // Returns the record at the start of `data` and its length on disk.  If `data` doesn't start
+    // with a valid record, returns the length the record claims (saturated to `usize::MAX` if it
+    // overflows), or `None` if it's incomplete.
+    fn parse_record(data: &[u8]) -> Result<(JournalRecord, usize), Option<usize>> {
+        if data.len() < LENGTH_SIZE {
+            return Err(None);
+        }
+        let length = data[..LENGTH_SIZE]
+                         .iter()
+                         .rev()
+                         .fold(0usize, |length, &byte| (length << 8) | byte as usize);
+        let total = match LENGTH_SIZE.checked_add(length)
+                                     .and_then(|total| total.checked_add(sha256::DIGESTBYTES)) {
+            Some(total) => total,
+            None => return Err(Some(usize::max_value())),
+        };
+        if data.len() < total {
+            return Err(None);
+        }
+        let serialised = &data[LENGTH_SIZE..LENGTH_SIZE + length];
+        if sha256::hash(serialised).0[..] != data[LENGTH_SIZE + length..total] {
+            return Err(Some(total));
+        }
+        deserialise(serialised).map(|record| (record, total)).map_err(|_| Some(total))
+    }
+
+    fn apply(entries: &mut MemoryMailboxStore, record: JournalRecord) -> Result<(), Error> {
+        match record {
+            JournalRecord::Put(owner, name, entry) => entries.put(&owner, &name, entry),
+            JournalRecord::Delete(owner, name) => entries.delete(&owner, &name).map(|_| ()),
+        }
+    }
+}
+
+impl MailboxStore for FileMailboxStore {
+    fn put(&mut self, owner: &XorName, name: &XorName, entry: MailboxEntry) -> Result<(), Error> {
+        let record = JournalRecord::Put(owner.clone(), name.clone(), entry);
+        try!(self.append(&record));
+        Self::apply(&mut self.entries, record)
+    }
+
+    fn get(&self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error> {
+        self.entries.get(owner, name)
+    }
+
+    fn delete(&mut self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error> {
+        if try!(self.entries.get(owner, name)).is_none() {
+            return Ok(None);
+        }
+        try!(self.append(&JournalRecord::Delete(owner.clone(), name.clone())));
+        self.entries.delete(owner, name)
+    }
+
+    fn list(&self, owner: &XorName) -> Result<Vec<XorName>, Error> {
+        self.entries.list(owner)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::JOURNAL_FILE;
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Read, Write};
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{Error, MailboxEntry, MailboxStore, MpidMessage};
+
+    #[test]
+    fn full() {
+        let dir = env::temp_dir().join(format!("safe_network_common_{}",
+                                               rand::random::<XorName>().as_hex()));
+        let (_, secret_key) = sign::gen_keypair();
+        let owner: XorName = rand::random();
+        let mut entries = vec![];
+        for _ in 0..3 {
+            let message = unwrap_result!(MpidMessage::new(owner.clone(),
+                                                          vec![],
+                                                          rand::random(),
+                                                          vec![1, 2, 3],
+                                                          &secret_key));
+            entries.push((unwrap_result!(message.name()), MailboxEntry::Message(message)));
+        }
+        entries.sort_by_key(|entry| entry.0.clone());
+        let names = entries.iter().map(|entry| entry.0.clone()).collect::<Vec<_>>();
+
+        // Mutations survive reopening the store.
+        {
+            let mut store = unwrap_result!(FileMailboxStore::open(&dir));
+            for &(ref name, ref entry) in &entries {
+                unwrap_result!(store.put(&owner, name, entry.clone()));
+            }
+            assert_eq!(unwrap_result!(store.delete(&owner, &names[0])),
+                       Some(entries[0].1.clone()));
+        }
+        {
+            let store = unwrap_result!(FileMailboxStore::open(&dir));
+            assert_eq!(unwrap_result!(store.list(&owner)), &names[1..]);
+            assert_eq!(unwrap_result!(store.get(&owner, &names[1])),
+                       Some(entries[1].1.clone()));
+        }
+
+        // A torn record at the end of the journal is discarded.
+        {
+            let mut journal = unwrap_result!(OpenOptions::new()
+                                                 .append(true)
+                                                 .open(dir.join(JOURNAL_FILE)));
+            unwrap_result!(journal.write_all(&[200, 0, 0, 0, 1, 2, 3]));
+        }
+        {
+            let mut store = unwrap_result!(FileMailboxStore::open(&dir));
+            assert_eq!(unwrap_result!(store.list(&owner)), &names[1..]);
+            unwrap_result!(store.put(&owner, &names[0], entries[0].1.clone()));
+            unwrap_result!(store.compact());
+            assert_eq!(unwrap_result!(fs::metadata(dir.join(JOURNAL_FILE))).len(), 0);
+            assert!(unwrap_result!(store.delete(&owner, &names[2])).is_some());
+        }
+        {
+            let store = unwrap_result!(FileMailboxStore::open(&dir));
+            assert_eq!(unwrap_result!(store.list(&owner)), &names[..2]);
+        }
+
+        // A corrupt record followed by valid ones fails opening rather than losing the latter.
+        let mut contents = vec![];
+        let _ = unwrap_result!(unwrap_result!(File::open(dir.join(JOURNAL_FILE)))
+                                   .read_to_end(&mut contents));
+        let mut corrupt = contents.clone();
+        corrupt[5] ^= 1;
+        corrupt.extend_from_slice(&contents);
+        unwrap_result!(unwrap_result!(File::create(dir.join(JOURNAL_FILE))).write_all(&corrupt));
+        match FileMailboxStore::open(&dir) {
+            Err(Error::Malformed) => (),
+            Ok(_) => panic!("Unexpected success"),
+            Err(error) => panic!("Unexpected error: {:?}", error),
+        }
+        assert_eq!(unwrap_result!(fs::metadata(dir.join(JOURNAL_FILE))).len() as usize,
+                   2 * contents.len());
+
+        unwrap_result!(fs::remove_dir_all(&dir));
+    }
+}
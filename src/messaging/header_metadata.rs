@@ -0,0 +1,297 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Version of the [`HeaderMetadata`](struct.HeaderMetadata.html) encoding, written as its first
+/// byte.
+pub const METADATA_VERSION: u8 = 1;
+/// The lowest tag available for application-defined
+/// [extension fields](struct.HeaderMetadata.html#structfield.extensions) (128).  Lower tags are
+/// reserved for well-known fields.
+pub const MIN_EXTENSION_TAG: u8 = 0x80;
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::str;
+
+use super::{Error, GUID_SIZE, MAX_HEADER_METADATA_SIZE, Priority};
+
+const SUBJECT_TAG: u8 = 1;
+const CONTENT_TYPE_TAG: u8 = 2;
+const PRIORITY_TAG: u8 = 3;
+const THREAD_ID_TAG: u8 = 4;
+const EXPIRY_TAG: u8 = 5;
+
+// Each field is encoded as a one-byte tag, a one-byte length, then the value.
+const FIELD_OVERHEAD: usize = 2;
+
+/// A structured form of [header `metadata`](struct.MpidHeader.html#method.metadata), so that
+/// applications can read each other's headers.
+///
+/// The encoding is the version byte followed by a sequence of tag-length-value fields in
+/// ascending tag order, each tag appearing at most once.  Only fields which are set are encoded.
+/// Strings are UTF-8, the priority is a single byte (0 for low, 1 for normal, 2 for high) and the
+/// expiry is a little-endian `u64` of seconds since the Unix epoch.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct HeaderMetadata {
+    /// The subject of the message, or the start of it.  See [`encode()`](#method.encode).
+    pub subject: Option<String>,
+    /// The type of the message's content, e.g. a MIME type.
+    pub content_type: Option<String>,
+    /// The priority of the message.
+    pub priority: Option<Priority>,
+    /// An identifier shared by all messages in a conversation, e.g. the GUID of the first.
+    pub thread_id: Option<[u8; GUID_SIZE]>,
+    /// The time, in seconds since the Unix epoch, after which the message may be discarded.
+    pub expiry: Option<u64>,
+    /// Application-defined fields, keyed by tags no lower than
+    /// [`MIN_EXTENSION_TAG`](constant.MIN_EXTENSION_TAG.html).
+    pub extensions: BTreeMap<u8, Vec<u8>>,
+}
+
+impl HeaderMetadata {
+    /// Constructs metadata with no fields set.
+    pub fn new() -> HeaderMetadata {
+        Default::default()
+    }
+
+    /// Encodes the fields such that they fit within
+    /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).  See
+    /// [`encode_within()`](#method.encode_within).
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        self.encode_within(MAX_HEADER_METADATA_SIZE)
+    }
+
+    /// Encodes the fields such that they fit within `limit` bytes, e.g.
+    /// [`MAX_ENCRYPTED_METADATA_SIZE`](constant.MAX_ENCRYPTED_METADATA_SIZE.html) for an encrypted
+    /// message.
+    ///
+    /// The subject is truncated (at a character boundary) as far as needed to fit.  An error is
+    /// returned if the fields don't fit even with an empty subject, or if an extension's tag is
+    /// lower than `MIN_EXTENSION_TAG`.
+    pub fn encode_within(&self, limit: usize) -> Result<Vec<u8>, Error> {
+        // All fields other than the subject, which is truncated to fit alongside them.
+        let mut rest = vec![];
+        if let Some(ref content_type) = self.content_type {
+            try!(push_field(&mut rest, CONTENT_TYPE_TAG, content_type.as_bytes()));
+        }
+        if let Some(priority) = self.priority {
+            try!(push_field(&mut rest, PRIORITY_TAG, &[encode_priority(priority)]));
+        }
+        if let Some(ref thread_id) = self.thread_id {
+            try!(push_field(&mut rest, THREAD_ID_TAG, thread_id));
+        }
+        if let Some(expiry) = self.expiry {
+            try!(push_field(&mut rest, EXPIRY_TAG, &encode_u64(expiry)));
+        }
+        for (&tag, value) in &self.extensions {
+            if tag < MIN_EXTENSION_TAG {
+                return Err(Error::Malformed);
+            }
+            try!(push_field(&mut rest, tag, value));
+        }
+
+        let mut encoded = vec![METADATA_VERSION];
+        if let Some(ref subject) = self.subject {
+            let available = match limit.checked_sub(encoded.len() + FIELD_OVERHEAD + rest.len()) {
+                Some(available) => available,
+                None => return Err(Error::MetadataTooLarge),
+            };
+            let subject_len = truncated_len(subject, available);
+            try!(push_field(&mut encoded, SUBJECT_TAG, &subject.as_bytes()[..subject_len]));
+        }
+        encoded.extend_from_slice(&rest);
+        if encoded.len() > limit {
+            return Err(Error::MetadataTooLarge);
+        }
+        Ok(encoded)
+    }
+
+    /// Decodes metadata produced by [`encode()`](#method.encode).
+    ///
+    /// An error will be returned if `encoded` exceeds
+    /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html), if its version isn't
+    /// [`METADATA_VERSION`](constant.METADATA_VERSION.html), or if it isn't a valid encoding, e.g.
+    /// where tags are repeated or out of order, or a reserved tag isn't recognised.
+    pub fn decode(encoded: &[u8]) -> Result<HeaderMetadata, Error> {
+        if encoded.len() > MAX_HEADER_METADATA_SIZE {
+            return Err(Error::MetadataTooLarge);
+        }
+        if encoded.first() != Some(&METADATA_VERSION) {
+            return Err(Error::Malformed);
+        }
+        let mut metadata = HeaderMetadata::new();
+        let mut remaining = &encoded[1..];
+        let mut previous_tag = None;
+        while !remaining.is_empty() {
+            if remaining.len() < FIELD_OVERHEAD {
+                return Err(Error::Malformed);
+            }
+            let tag = remaining[0];
+            let length = remaining[1] as usize;
+            if previous_tag.map_or(false, |previous| tag <= previous) ||
+               remaining.len() < FIELD_OVERHEAD + length {
+                return Err(Error::Malformed);
+            }
+            previous_tag = Some(tag);
+            let value = &remaining[FIELD_OVERHEAD..FIELD_OVERHEAD + length];
+            remaining = &remaining[FIELD_OVERHEAD + length..];
+            match tag {
+                SUBJECT_TAG => metadata.subject = Some(try!(decode_string(value))),
+                CONTENT_TYPE_TAG => metadata.content_type = Some(try!(decode_string(value))),
+                PRIORITY_TAG => metadata.priority = Some(try!(decode_priority(value))),
+                THREAD_ID_TAG => {
+                    if value.len() != GUID_SIZE {
+                        return Err(Error::Malformed);
+                    }
+                    let mut thread_id = [0u8; GUID_SIZE];
+                    thread_id.copy_from_slice(value);
+                    metadata.thread_id = Some(thread_id);
+                }
+                EXPIRY_TAG => metadata.expiry = Some(try!(decode_u64(value))),
+                _ if tag >= MIN_EXTENSION_TAG => {
+                    let _ = metadata.extensions.insert(tag, value.to_vec());
+                }
+                _ => return Err(Error::Malformed),
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+fn push_field(encoded: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(), Error> {
+    if value.len() > u8::max_value() as usize {
+        return Err(Error::MetadataTooLarge);
+    }
+    encoded.push(tag);
+    encoded.push(value.len() as u8);
+    encoded.extend_from_slice(value);
+    Ok(())
+}
+
+// The length of the longest prefix of `value` not exceeding `max_len` bytes which ends on a
+// character boundary.
+fn truncated_len(value: &str, max_len: usize) -> usize {
+    let mut len = cmp::min(value.len(), cmp::min(max_len, u8::max_value() as usize));
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+fn decode_string(value: &[u8]) -> Result<String, Error> {
+    str::from_utf8(value).map(|value| value.to_owned()).map_err(|_| Error::Malformed)
+}
+
+fn encode_u64(value: u64) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = (value >> (8 * index)) as u8;
+    }
+    bytes
+}
+
+fn decode_u64(value: &[u8]) -> Result<u64, Error> {
+    if value.len() != 8 {
+        return Err(Error::Malformed);
+    }
+    Ok(value.iter().rev().fold(0, |decoded, &byte| decoded << 8 | byte as u64))
+}
+
+fn encode_priority(priority: Priority) -> u8 {
+    match priority {
+        Priority::Low => 0,
+        Priority::Normal => 1,
+        Priority::High => 2,
+    }
+}
+
+fn decode_priority(value: &[u8]) -> Result<Priority, Error> {
+    if value.len() != 1 {
+        return Err(Error::Malformed);
+    }
+    match value[0] {
+        0 => Ok(Priority::Low),
+        1 => Ok(Priority::Normal),
+        2 => Ok(Priority::High),
+        _ => Err(Error::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use messaging::{Error, GUID_SIZE, MAX_ENCRYPTED_METADATA_SIZE, MAX_HEADER_METADATA_SIZE,
+                    Priority};
+
+    #[test]
+    fn full() {
+        // Empty metadata is just the version.
+        let empty = HeaderMetadata::new();
+        assert_eq!(unwrap_result!(empty.encode()), vec![METADATA_VERSION]);
+        assert_eq!(unwrap_result!(HeaderMetadata::decode(&[METADATA_VERSION])), empty);
+
+        let mut metadata = HeaderMetadata::new();
+        metadata.subject = Some("Hello".to_owned());
+        metadata.content_type = Some("text/plain".to_owned());
+        metadata.priority = Some(Priority::High);
+        metadata.thread_id = Some([9u8; GUID_SIZE]);
+        metadata.expiry = Some(0x0102030405060708);
+        let _ = metadata.extensions.insert(MIN_EXTENSION_TAG, vec![1, 2, 3]);
+        let encoded = unwrap_result!(metadata.encode());
+        assert_eq!(&encoded[..8], &[METADATA_VERSION, 1, 5, b'H', b'e', b'l', b'l', b'o']);
+        assert_eq!(unwrap_result!(HeaderMetadata::decode(&encoded)), metadata);
+
+        // Long subjects are truncated at a character boundary to fit within the limit.
+        metadata.subject = Some((0..100).map(|_| '\u{e9}').collect());
+        for &limit in &[MAX_HEADER_METADATA_SIZE, MAX_ENCRYPTED_METADATA_SIZE] {
+            let encoded = unwrap_result!(metadata.encode_within(limit));
+            assert!(encoded.len() <= limit && encoded.len() >= limit - 1);
+            let decoded = unwrap_result!(HeaderMetadata::decode(&encoded));
+            let subject = unwrap_result!(decoded.subject.ok_or(()));
+            assert!(unwrap_result!(metadata.subject.as_ref().ok_or(())).starts_with(&subject));
+        }
+
+        // Other fields aren't truncated.
+        let _ = metadata.extensions.insert(0xff, vec![0; MAX_HEADER_METADATA_SIZE]);
+        match metadata.encode() {
+            Err(Error::MetadataTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let mut reserved = HeaderMetadata::new();
+        let _ = reserved.extensions.insert(MIN_EXTENSION_TAG - 1, vec![]);
+        match reserved.encode() {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Invalid encodings are rejected.
+        let invalid: Vec<Vec<u8>> = vec![vec![],
+                                         vec![METADATA_VERSION + 1],
+                                         vec![METADATA_VERSION, 1],
+                                         vec![METADATA_VERSION, 1, 2, b'a'],
+                                         vec![METADATA_VERSION, 2, 0, 1, 0],
+                                         vec![METADATA_VERSION, 1, 0, 1, 0],
+                                         vec![METADATA_VERSION, 3, 1, 3],
+                                         vec![METADATA_VERSION, 5, 1, 0],
+                                         vec![METADATA_VERSION, 6, 0],
+                                         vec![METADATA_VERSION, 1, 1, 0xff],
+                                         vec![METADATA_VERSION; MAX_HEADER_METADATA_SIZE + 1]];
+        for encoded in &invalid {
+            assert!(HeaderMetadata::decode(encoded).is_err());
+        }
+    }
+}
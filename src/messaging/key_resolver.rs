@@ -0,0 +1,245 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use sodiumoxide::crypto::sign::PublicKey;
+use super::MpidPublicId;
+use xor_name::XorName;
+
+/// Maps the name of an MPID account to its public signing keys, for use with e.g.
+/// [`MpidHeader::verify_with_resolver()`](struct.MpidHeader.html#method.verify_with_resolver).
+///
+/// An account may have more than one valid key, e.g. while a key is being rotated; a signature is
+/// accepted if it verifies against any of them.
+pub trait KeyResolver {
+    /// Returns the public keys of the account called `name`, or an empty vector if none are known.
+    fn resolve(&self, name: &XorName) -> Vec<PublicKey>;
+}
+
+impl<'a, R: KeyResolver + ?Sized> KeyResolver for &'a R {
+    fn resolve(&self, name: &XorName) -> Vec<PublicKey> {
+        (**self).resolve(name)
+    }
+}
+
+/// A `KeyResolver` holding its keys in memory.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryKeyResolver {
+    keys: HashMap<XorName, Vec<PublicKey>>,
+}
+
+impl MemoryKeyResolver {
+    /// Constructs an empty resolver.
+    pub fn new() -> MemoryKeyResolver {
+        Default::default()
+    }
+
+    /// Adds `public_key` to the keys of the account called `name`.
+    pub fn insert(&mut self, name: XorName, public_key: PublicKey) {
+        let keys = self.keys.entry(name).or_insert_with(Vec::new);
+        if !keys.contains(&public_key) {
+            keys.push(public_key);
+        }
+    }
+
+    /// Adds the signing key of `public_id` to the keys of the account it names.
+    pub fn insert_public_id(&mut self, public_id: &MpidPublicId) {
+        self.insert(public_id.name(), *public_id.signing_key())
+    }
+
+    /// Removes and returns all keys of the account called `name`.
+    pub fn remove(&mut self, name: &XorName) -> Vec<PublicKey> {
+        self.keys.remove(name).unwrap_or_else(Vec::new)
+    }
+}
+
+impl KeyResolver for MemoryKeyResolver {
+    fn resolve(&self, name: &XorName) -> Vec<PublicKey> {
+        self.keys.get(name).cloned().unwrap_or_else(Vec::new)
+    }
+}
+
+/// A `KeyResolver` which caches the results of a slower one, e.g. one which looks keys up on the
+/// network.
+///
+/// At most `capacity` accounts are cached; beyond that an arbitrary entry is evicted.  Unless
+/// constructed via [`with_empty_results()`](#method.with_empty_results), empty results aren't
+/// cached, so an account's keys are found as soon as the inner resolver knows them.
+pub struct CachingKeyResolver<R: KeyResolver> {
+    inner: R,
+    capacity: usize,
+    cache_empty_results: bool,
+    cache: RefCell<HashMap<XorName, Vec<PublicKey>>>,
+}
+
+impl<R: KeyResolver> CachingKeyResolver<R> {
+    /// Constructs a cache of at most `capacity` accounts in front of `inner`.
+    pub fn new(inner: R, capacity: usize) -> CachingKeyResolver<R> {
+        CachingKeyResolver {
+            inner: inner,
+            capacity: capacity,
+            cache_empty_results: false,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Constructs a cache as per [`new()`](#method.new) which also caches empty results, so that
+    /// unknown accounts are only looked up once until invalidated.
+    pub fn with_empty_results(inner: R, capacity: usize) -> CachingKeyResolver<R> {
+        CachingKeyResolver { cache_empty_results: true, ..CachingKeyResolver::new(inner, capacity) }
+    }
+
+    /// Discards any cached keys of the account called `name`, e.g. after it has revoked a key.
+    pub fn invalidate(&self, name: &XorName) {
+        let _ = self.cache.borrow_mut().remove(name);
+    }
+
+    /// Discards all cached keys.
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// The number of accounts currently cached.
+    pub fn len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    /// Returns whether no accounts are currently cached.
+    pub fn is_empty(&self) -> bool {
+        self.cache.borrow().is_empty()
+    }
+
+    /// Returns the inner resolver.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: KeyResolver> KeyResolver for CachingKeyResolver<R> {
+    fn resolve(&self, name: &XorName) -> Vec<PublicKey> {
+        if let Some(keys) = self.cache.borrow().get(name) {
+            return keys.clone();
+        }
+        let keys = self.inner.resolve(name);
+        if (keys.is_empty() && !self.cache_empty_results) || self.capacity == 0 {
+            return keys;
+        }
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() >= self.capacity {
+            if let Some(evicted) = cache.keys().next().cloned() {
+                let _ = cache.remove(&evicted);
+            }
+        }
+        let _ = cache.insert(*name, keys.clone());
+        keys
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use messaging::{MpidHeader, MpidMessage, MpidMessageWrapper};
+    use rand;
+    use sodiumoxide::crypto::sign::{self, PublicKey};
+    use xor_name::XorName;
+
+    // Counts the lookups made of the inner resolver.
+    struct CountingResolver {
+        inner: MemoryKeyResolver,
+        lookups: Cell<usize>,
+    }
+
+    impl KeyResolver for CountingResolver {
+        fn resolve(&self, name: &XorName) -> Vec<PublicKey> {
+            self.lookups.set(self.lookups.get() + 1);
+            self.inner.resolve(name)
+        }
+    }
+
+    #[test]
+    fn full() {
+        let (old_public_key, old_secret_key) = sign::gen_keypair();
+        let (new_public_key, new_secret_key) = sign::gen_keypair();
+        let (_, other_secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender, old_public_key);
+        resolver.insert(sender, new_public_key);
+        resolver.insert(sender, new_public_key);
+        assert_eq!(resolver.resolve(&sender), vec![old_public_key, new_public_key]);
+
+        // Headers and messages signed with any of the sender's keys verify.
+        let old_header = unwrap_result!(MpidHeader::new(sender, vec![], &old_secret_key));
+        let new_header = unwrap_result!(MpidHeader::new(sender, vec![], &new_secret_key));
+        let forged_header = unwrap_result!(MpidHeader::new(sender, vec![], &other_secret_key));
+        assert!(old_header.verify_with_resolver(&resolver));
+        assert!(new_header.verify_with_resolver(&resolver));
+        assert!(!forged_header.verify_with_resolver(&resolver));
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      rand::random(),
+                                                      vec![1],
+                                                      &new_secret_key));
+        assert!(message.verify_with_resolver(&resolver));
+
+        // Unknown senders fail.
+        let unknown = unwrap_result!(MpidHeader::new(rand::random(), vec![], &new_secret_key));
+        assert!(!unknown.verify_with_resolver(&resolver));
+
+        // Batches of wrappers are checked individually, looking up each sender once.
+        let counting = CountingResolver {
+            inner: resolver,
+            lookups: Cell::new(0),
+        };
+        let wrappers = vec![MpidMessageWrapper::PutHeader(old_header.clone()),
+                            MpidMessageWrapper::PutMessage(message.clone()),
+                            MpidMessageWrapper::GetOutboxHeadersResponse(vec![new_header,
+                                                                              forged_header]),
+                            MpidMessageWrapper::Online,
+                            MpidMessageWrapper::PutHeader(unknown.clone()),
+                            MpidMessageWrapper::PutHeader(unknown.clone())];
+        assert_eq!(MpidMessageWrapper::verify_batch_with_resolver(&wrappers, &counting),
+                   vec![true, true, false, true, false, false]);
+        assert_eq!(counting.lookups.get(), 2);
+
+        // Cached keys are used until invalidated, while unknown senders are looked up each time.
+        counting.lookups.set(0);
+        let caching = CachingKeyResolver::new(counting, 1);
+        assert!(old_header.verify_with_resolver(&caching));
+        assert!(message.verify_with_resolver(&caching));
+        assert_eq!(caching.len(), 1);
+        caching.invalidate(&sender);
+        assert!(caching.is_empty());
+        assert!(old_header.verify_with_resolver(&caching));
+        assert!(!unknown.verify_with_resolver(&caching));
+        assert!(!unknown.verify_with_resolver(&caching));
+        assert_eq!(caching.len(), 1);
+        let counting = caching.into_inner();
+        assert_eq!(counting.lookups.get(), 4);
+
+        // Empty results may be cached too.
+        counting.lookups.set(0);
+        let caching = CachingKeyResolver::with_empty_results(counting, 1);
+        assert!(!unknown.verify_with_resolver(&caching));
+        assert!(!unknown.verify_with_resolver(&caching));
+        assert_eq!(caching.len(), 1);
+        assert_eq!(caching.into_inner().lookups.get(), 1);
+    }
+}
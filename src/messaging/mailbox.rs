@@ -0,0 +1,416 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::collections::{BTreeMap, Bound};
+use std::collections::btree_map::Values;
+
+use maidsafe_utilities::serialisation::serialise;
+use rustc_serialize::Encodable;
+use super::{Error, MAX_HEADER_PAGE_SIZE, MAX_INBOX_SIZE, MAX_OUTBOX_SIZE, MpidHeader,
+            MpidMessage};
+use xor_name::XorName;
+
+/// A bounded portion of the headers held in an inbox or outbox, ordered by header name.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct HeaderPage {
+    /// The headers in this page, in ascending order of header name.
+    pub headers: Vec<MpidHeader>,
+    /// The name of the last header in this page if further headers follow it, to be passed as the
+    /// cursor when requesting the next page.  `None` if this is the final page.
+    pub next_cursor: Option<XorName>,
+}
+
+// Entries keyed by name, with the total serialised size of the entries tracked against a limit.
+#[derive(Clone)]
+struct Mailbox<T> {
+    entries: BTreeMap<XorName, (T, usize)>,
+    used_space: usize,
+    limit: usize,
+}
+
+impl<T: Encodable> Mailbox<T> {
+    fn new(limit: usize) -> Mailbox<T> {
+        Mailbox {
+            entries: BTreeMap::new(),
+            used_space: 0,
+            limit: limit,
+        }
+    }
+
+    // Returns `Ok(None)` if the entry doesn't fit in the remaining space.
+    fn insert(&mut self, name: XorName, entry: T) -> Result<Option<bool>, Error> {
+        if self.entries.contains_key(&name) {
+            return Ok(Some(false));
+        }
+        let size = try!(serialise(&entry)).len();
+        if size > self.remaining_space() {
+            return Ok(None);
+        }
+        self.used_space += size;
+        let _ = self.entries.insert(name, (entry, size));
+        Ok(Some(true))
+    }
+
+    fn remove(&mut self, name: &XorName) -> Option<T> {
+        self.entries.remove(name).map(|(entry, size)| {
+            self.used_space -= size;
+            entry
+        })
+    }
+
+    fn get(&self, name: &XorName) -> Option<&T> {
+        self.entries.get(name).map(|entry| &entry.0)
+    }
+
+    fn remaining_space(&self) -> usize {
+        self.limit - self.used_space
+    }
+
+    fn expired<F>(&self, now: u64, header_of: F) -> Vec<XorName>
+        where F: Fn(&T) -> &MpidHeader
+    {
+        self.entries
+            .iter()
+            .filter(|&(_, entry)| header_of(&entry.0).is_expired(now))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    fn page<F>(&self, start_after: Option<&XorName>, page_size: usize, header_of: F) -> HeaderPage
+        where F: Fn(&T) -> MpidHeader
+    {
+        let page_size = cmp::max(1, cmp::min(page_size, MAX_HEADER_PAGE_SIZE));
+        let lower_bound = match start_after {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Unbounded,
+        };
+        let mut remaining = self.entries.range((lower_bound, Bound::Unbounded));
+        let mut headers = Vec::with_capacity(page_size);
+        let mut last_name = None;
+        for (name, entry) in remaining.by_ref().take(page_size) {
+            headers.push(header_of(&entry.0));
+            last_name = Some(name);
+        }
+        HeaderPage {
+            headers: headers,
+            next_cursor: remaining.next().and(last_name.cloned()),
+        }
+    }
+}
+
+/// The headers of messages sent to an account, keyed by header name.
+///
+/// The serialised size of every header is counted against
+/// [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html).
+#[derive(Clone)]
+pub struct Inbox {
+    mailbox: Mailbox<MpidHeader>,
+}
+
+impl Inbox {
+    /// Constructs an empty inbox.
+    pub fn new() -> Inbox {
+        Inbox { mailbox: Mailbox::new(MAX_INBOX_SIZE) }
+    }
+
+    /// Adds `header`, returning `Ok(false)` if a header with the same name is already held.
+    ///
+    /// Returns `Error::InboxFull` if the serialised header doesn't fit in the remaining space.
+    pub fn insert(&mut self, header: MpidHeader) -> Result<bool, Error> {
+        let name = try!(header.name());
+        match try!(self.mailbox.insert(name, header)) {
+            Some(inserted) => Ok(inserted),
+            None => Err(Error::InboxFull),
+        }
+    }
+
+    /// Removes and returns the named header.
+    pub fn remove(&mut self, name: &XorName) -> Option<MpidHeader> {
+        self.mailbox.remove(name)
+    }
+
+    /// Returns the names of all headers which have expired by `now`, in seconds since the Unix
+    /// epoch, ordered by name.
+    pub fn expired(&self, now: u64) -> Vec<XorName> {
+        self.mailbox.expired(now, |header| header)
+    }
+
+    /// Returns the named header.
+    pub fn get(&self, name: &XorName) -> Option<&MpidHeader> {
+        self.mailbox.get(name)
+    }
+
+    /// Returns whether the named header is held.
+    pub fn contains(&self, name: &XorName) -> bool {
+        self.mailbox.entries.contains_key(name)
+    }
+
+    /// Returns an iterator over all headers, ordered by header name.
+    pub fn headers<'a>(&'a self) -> InboxHeaders<'a> {
+        InboxHeaders { values: self.mailbox.entries.values() }
+    }
+
+    /// Returns up to `page_size` headers whose names follow `start_after`, or from the start if
+    /// `start_after` is `None`.  `page_size` is clamped to the range 1 to
+    /// [`MAX_HEADER_PAGE_SIZE`](constant.MAX_HEADER_PAGE_SIZE.html).
+    pub fn headers_page(&self, start_after: Option<&XorName>, page_size: usize) -> HeaderPage {
+        self.mailbox.page(start_after, page_size, |header| header.clone())
+    }
+
+    /// The number of headers held.
+    pub fn len(&self) -> usize {
+        self.mailbox.entries.len()
+    }
+
+    /// Returns whether no headers are held.
+    pub fn is_empty(&self) -> bool {
+        self.mailbox.entries.is_empty()
+    }
+
+    /// The total serialised size in bytes of all headers held.
+    pub fn used_space(&self) -> usize {
+        self.mailbox.used_space
+    }
+
+    /// The number of bytes which can still be added before the inbox is full.
+    pub fn remaining_space(&self) -> usize {
+        self.mailbox.remaining_space()
+    }
+}
+
+impl Default for Inbox {
+    fn default() -> Inbox {
+        Inbox::new()
+    }
+}
+
+/// Iterator over the headers in an [`Inbox`](struct.Inbox.html).
+pub struct InboxHeaders<'a> {
+    values: Values<'a, XorName, (MpidHeader, usize)>,
+}
+
+impl<'a> Iterator for InboxHeaders<'a> {
+    type Item = &'a MpidHeader;
+
+    fn next(&mut self) -> Option<&'a MpidHeader> {
+        self.values.next().map(|entry| &entry.0)
+    }
+}
+
+/// The messages sent by an account which haven't yet been deleted, keyed by header name.
+///
+/// The serialised size of every message is counted against
+/// [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html).
+#[derive(Clone)]
+pub struct Outbox {
+    mailbox: Mailbox<MpidMessage>,
+}
+
+impl Outbox {
+    /// Constructs an empty outbox.
+    pub fn new() -> Outbox {
+        Outbox { mailbox: Mailbox::new(MAX_OUTBOX_SIZE) }
+    }
+
+    /// Adds `message`, returning `Ok(false)` if a message with the same name is already held.
+    ///
+    /// Returns `Error::OutboxFull` if the serialised message doesn't fit in the remaining space.
+    pub fn insert(&mut self, message: MpidMessage) -> Result<bool, Error> {
+        let name = try!(message.name());
+        match try!(self.mailbox.insert(name, message)) {
+            Some(inserted) => Ok(inserted),
+            None => Err(Error::OutboxFull),
+        }
+    }
+
+    /// Removes and returns the named message.
+    pub fn remove(&mut self, name: &XorName) -> Option<MpidMessage> {
+        self.mailbox.remove(name)
+    }
+
+    /// Returns the names of all messages whose headers have expired by `now`, in seconds since the
+    /// Unix epoch, ordered by name.
+    pub fn expired(&self, now: u64) -> Vec<XorName> {
+        self.mailbox.expired(now, |message| message.header())
+    }
+
+    /// Returns the named message.
+    pub fn get(&self, name: &XorName) -> Option<&MpidMessage> {
+        self.mailbox.get(name)
+    }
+
+    /// Returns whether the named message is held.
+    pub fn contains(&self, name: &XorName) -> bool {
+        self.mailbox.entries.contains_key(name)
+    }
+
+    /// Returns an iterator over all messages, ordered by header name.
+    pub fn messages<'a>(&'a self) -> OutboxMessages<'a> {
+        OutboxMessages { values: self.mailbox.entries.values() }
+    }
+
+    /// Returns the headers of up to `page_size` messages whose names follow `start_after`, or from
+    /// the start if `start_after` is `None`.  `page_size` is clamped to the range 1 to
+    /// [`MAX_HEADER_PAGE_SIZE`](constant.MAX_HEADER_PAGE_SIZE.html).
+    pub fn headers_page(&self, start_after: Option<&XorName>, page_size: usize) -> HeaderPage {
+        self.mailbox.page(start_after, page_size, |message| message.header().clone())
+    }
+
+    /// The number of messages held.
+    pub fn len(&self) -> usize {
+        self.mailbox.entries.len()
+    }
+
+    /// Returns whether no messages are held.
+    pub fn is_empty(&self) -> bool {
+        self.mailbox.entries.is_empty()
+    }
+
+    /// The total serialised size in bytes of all messages held.
+    pub fn used_space(&self) -> usize {
+        self.mailbox.used_space
+    }
+
+    /// The number of bytes which can still be added before the outbox is full.
+    pub fn remaining_space(&self) -> usize {
+        self.mailbox.remaining_space()
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Outbox {
+        Outbox::new()
+    }
+}
+
+/// Iterator over the messages in an [`Outbox`](struct.Outbox.html).
+pub struct OutboxMessages<'a> {
+    values: Values<'a, XorName, (MpidMessage, usize)>,
+}
+
+impl<'a> Iterator for OutboxMessages<'a> {
+    type Item = &'a MpidMessage;
+
+    fn next(&mut self) -> Option<&'a MpidMessage> {
+        self.values.next().map(|entry| &entry.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Mailbox;
+    use maidsafe_utilities::serialisation::serialise;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{self, MAX_BODY_SIZE, MAX_HEADER_PAGE_SIZE, MAX_INBOX_SIZE, MAX_OUTBOX_SIZE,
+                    MpidHeader, MpidMessage, MpidMessageBuilder};
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+
+        // Inbox usage matches the serialised sizes of the headers.
+        let mut inbox = Inbox::new();
+        assert!(inbox.is_empty());
+        assert_eq!(inbox.remaining_space(), MAX_INBOX_SIZE);
+        let header = unwrap_result!(MpidHeader::new(sender.clone(), vec![1, 2, 3], &secret_key));
+        let header_size = unwrap_result!(serialise(&header)).len();
+        assert!(unwrap_result!(inbox.insert(header.clone())));
+        assert!(!unwrap_result!(inbox.insert(header.clone())));
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox.used_space(), header_size);
+        assert_eq!(inbox.remaining_space(), MAX_INBOX_SIZE - header_size);
+        let name = unwrap_result!(header.name());
+        assert!(inbox.contains(&name));
+        assert_eq!(inbox.headers().collect::<Vec<_>>(), vec![&header]);
+        assert_eq!(inbox.remove(&name), Some(header));
+        assert!(inbox.remove(&name).is_none());
+        assert_eq!(inbox.used_space(), 0);
+
+        // Headers are paged in order of name.  Headers with empty metadata still use space.
+        let mut headers = vec![];
+        for _ in 0..5 {
+            let header = unwrap_result!(MpidHeader::new(sender.clone(), vec![], &secret_key));
+            let used_space = inbox.used_space();
+            assert!(unwrap_result!(inbox.insert(header.clone())));
+            assert_eq!(inbox.used_space(),
+                       used_space + unwrap_result!(serialise(&header)).len());
+            assert!(inbox.used_space() > used_space);
+            headers.push((unwrap_result!(header.name()), header));
+        }
+        headers.sort_by_key(|entry| entry.0.clone());
+        let mut paged = vec![];
+        let mut cursor = None;
+        loop {
+            let page = inbox.headers_page(cursor.as_ref(), 2);
+            assert!(!page.headers.is_empty() && page.headers.len() <= 2);
+            paged.extend(page.headers);
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(paged,
+                   headers.iter().map(|entry| entry.1.clone()).collect::<Vec<_>>());
+        let page = inbox.headers_page(Some(&headers[3].0), 0);
+        assert_eq!(page.headers, vec![headers[4].1.clone()]);
+        assert!(page.next_cursor.is_none());
+        assert_eq!(inbox.headers_page(None, MAX_HEADER_PAGE_SIZE + 1).headers.len(), 5);
+
+        // Outbox usage matches the serialised sizes of the messages.
+        let mut outbox = Outbox::new();
+        let body = messaging::generate_random_bytes(MAX_BODY_SIZE);
+        let message = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                      vec![],
+                                                      rand::random(),
+                                                      body,
+                                                      &secret_key));
+        let message_size = unwrap_result!(serialise(&message)).len();
+        assert!(message_size > MAX_BODY_SIZE);
+        assert!(unwrap_result!(outbox.insert(message.clone())));
+        assert_eq!(outbox.used_space(), message_size);
+        assert_eq!(outbox.remaining_space(), MAX_OUTBOX_SIZE - message_size);
+        assert_eq!(outbox.messages().collect::<Vec<_>>(), vec![&message]);
+
+        // Only entries which have expired by the given time are reported.
+        let expiry = messaging::seconds_since_epoch() + 100;
+        let expiring = unwrap_result!(MpidMessageBuilder::new()
+                                          .sender(sender.clone())
+                                          .recipient(rand::random())
+                                          .expiry(expiry)
+                                          .build(&secret_key));
+        let expiring_name = unwrap_result!(expiring.name());
+        assert!(unwrap_result!(outbox.insert(expiring.clone())));
+        assert!(unwrap_result!(inbox.insert(expiring.header().clone())));
+        assert!(outbox.expired(expiry - 1).is_empty());
+        assert!(inbox.expired(expiry - 1).is_empty());
+        assert_eq!(outbox.expired(expiry), vec![expiring_name.clone()]);
+        assert_eq!(inbox.expired(expiry), vec![expiring_name]);
+
+        // Entries which don't fit in the remaining space are rejected, leaving usage unchanged.
+        let mut mailbox = Mailbox::new(2 * message_size - 1);
+        assert_eq!(unwrap_result!(mailbox.insert(rand::random(), message.clone())),
+                   Some(true));
+        assert_eq!(unwrap_result!(mailbox.insert(rand::random(), message.clone())), None);
+        assert_eq!(mailbox.used_space, message_size);
+        assert_eq!(mailbox.remaining_space(), message_size - 1);
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::BTreeMap;
+
+use super::{Error, MpidHeader, MpidMessage};
+use xor_name::XorName;
+
+/// An entry held by a [`MailboxStore`](trait.MailboxStore.html): either a header in an account's
+/// inbox or a message in its outbox.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub enum MailboxEntry {
+    /// A header in the owner's inbox.
+    Header(MpidHeader),
+    /// A message in the owner's outbox.
+    Message(MpidMessage),
+}
+
+/// Persistent storage of inbox and outbox entries, keyed by owner name and header name.
+pub trait MailboxStore {
+    /// Stores `entry` under `owner` and `name`, replacing any existing entry.
+    fn put(&mut self, owner: &XorName, name: &XorName, entry: MailboxEntry) -> Result<(), Error>;
+
+    /// Returns the entry stored under `owner` and `name`.
+    fn get(&self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error>;
+
+    /// Removes and returns the entry stored under `owner` and `name`.
+    fn delete(&mut self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error>;
+
+    /// Returns the names of all entries stored under `owner`, ordered by name.
+    fn list(&self, owner: &XorName) -> Result<Vec<XorName>, Error>;
+}
+
+/// A [`MailboxStore`](trait.MailboxStore.html) held entirely in memory.
+#[derive(Clone, Default, RustcDecodable, RustcEncodable)]
+pub struct MemoryMailboxStore {
+    accounts: BTreeMap<XorName, BTreeMap<XorName, MailboxEntry>>,
+}
+
+impl MemoryMailboxStore {
+    /// Constructs an empty store.
+    pub fn new() -> MemoryMailboxStore {
+        MemoryMailboxStore { accounts: BTreeMap::new() }
+    }
+}
+
+impl MailboxStore for MemoryMailboxStore {
+    fn put(&mut self, owner: &XorName, name: &XorName, entry: MailboxEntry) -> Result<(), Error> {
+        let _ = self.accounts
+                    .entry(owner.clone())
+                    .or_insert_with(BTreeMap::new)
+                    .insert(name.clone(), entry);
+        Ok(())
+    }
+
+    fn get(&self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error> {
+        Ok(self.accounts.get(owner).and_then(|entries| entries.get(name)).cloned())
+    }
+
+    fn delete(&mut self, owner: &XorName, name: &XorName) -> Result<Option<MailboxEntry>, Error> {
+        let (entry, now_empty) = match self.accounts.get_mut(owner) {
+            Some(entries) => (entries.remove(name), entries.is_empty()),
+            None => return Ok(None),
+        };
+        if now_empty {
+            let _ = self.accounts.remove(owner);
+        }
+        Ok(entry)
+    }
+
+    fn list(&self, owner: &XorName) -> Result<Vec<XorName>, Error> {
+        Ok(self.accounts
+               .get(owner)
+               .map_or_else(Vec::new, |entries| entries.keys().cloned().collect()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{MpidHeader, MpidMessage};
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let owner: XorName = rand::random();
+        let header = unwrap_result!(MpidHeader::new(rand::random(), vec![], &secret_key));
+        let header_name = unwrap_result!(header.name());
+        let message = unwrap_result!(MpidMessage::new(owner.clone(),
+                                                      vec![],
+                                                      rand::random(),
+                                                      vec![1, 2, 3],
+                                                      &secret_key));
+        let message_name = unwrap_result!(message.name());
+
+        let mut store = MemoryMailboxStore::new();
+        assert!(unwrap_result!(store.list(&owner)).is_empty());
+        unwrap_result!(store.put(&owner, &header_name, MailboxEntry::Header(header.clone())));
+        unwrap_result!(store.put(&owner, &message_name, MailboxEntry::Message(message.clone())));
+        let mut names = vec![header_name.clone(), message_name.clone()];
+        names.sort();
+        assert_eq!(unwrap_result!(store.list(&owner)), names);
+        assert!(unwrap_result!(store.list(&rand::random())).is_empty());
+        assert_eq!(unwrap_result!(store.get(&owner, &header_name)),
+                   Some(MailboxEntry::Header(header.clone())));
+        assert!(unwrap_result!(store.get(&rand::random(), &header_name)).is_none());
+
+        assert_eq!(unwrap_result!(store.delete(&owner, &header_name)),
+                   Some(MailboxEntry::Header(header)));
+        assert!(unwrap_result!(store.delete(&owner, &header_name)).is_none());
+        assert_eq!(unwrap_result!(store.list(&owner)), vec![message_name]);
+    }
+}
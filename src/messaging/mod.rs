@@ -21,18 +21,115 @@ pub const GUID_SIZE: usize = 16;
 pub const MAX_INBOX_SIZE: usize = 1 << 27;
 /// Maximum allowed outbox size for an account (128 MiB).
 pub const MAX_OUTBOX_SIZE: usize = 1 << 27;
+/// Maximum number of headers returned in a single [`HeaderPage`](struct.HeaderPage.html) (200),
+/// chosen so that a full page of maximum-sized headers fits within a single network message.
+pub const MAX_HEADER_PAGE_SIZE: usize = 200;
 
+mod attachment;
+mod chunked_message;
+mod compression;
 mod error;
+mod file_mailbox_store;
+mod header_metadata;
+mod key_resolver;
+mod mailbox;
+mod mailbox_store;
 mod mpid_header;
+mod mpid_manager;
 mod mpid_message;
+mod mpid_message_builder;
 mod mpid_message_wrapper;
+mod mpid_public_id;
+mod multipart_body;
+mod padding;
+mod replay_filter;
+mod signing;
 
+pub use self::attachment::{AttachmentKey, AttachmentRef, ATTACHMENTS_VERSION};
+pub use self::chunked_message::{ChunkManifest, ChunkedMessage, CHUNK_MANIFEST_VERSION,
+                                 MAX_CHUNK_COUNT, MAX_CHUNKED_PAYLOAD_SIZE,
+                                 MAX_ENCRYPTED_CHUNKED_PAYLOAD_SIZE};
+pub use self::compression::{Codec, MAX_DECOMPRESSED_BODY_SIZE, SUPPORTED_CODECS};
 pub use self::error::Error;
-pub use self::mpid_message_wrapper::MpidMessageWrapper;
-pub use self::mpid_message::{MpidMessage, MAX_BODY_SIZE};
-pub use self::mpid_header::{MpidHeader, MAX_HEADER_METADATA_SIZE};
+pub use self::file_mailbox_store::FileMailboxStore;
+pub use self::header_metadata::{HeaderMetadata, METADATA_VERSION, MIN_EXTENSION_TAG};
+pub use self::key_resolver::{CachingKeyResolver, KeyResolver, MemoryKeyResolver};
+pub use self::mailbox::{HeaderPage, Inbox, InboxHeaders, Outbox, OutboxMessages};
+pub use self::mailbox_store::{MailboxEntry, MailboxStore, MemoryMailboxStore};
+pub use self::mpid_manager::{Action, MpidManager};
+pub use self::mpid_message_wrapper::{MpidMessageWrapper, MAX_SERIALISED_WRAPPER_SIZE};
+pub use self::mpid_message_builder::{MpidMessageBuilder, ValidationError};
+pub use self::mpid_message::{MpidMessage, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE,
+                             MAX_SERIALISED_MESSAGE_SIZE};
+pub use self::mpid_public_id::MpidPublicId;
+pub use self::mpid_header::{ClockSkewPolicy, MpidHeader, Priority, MAX_ENCRYPTED_METADATA_SIZE,
+                            MAX_HEADER_METADATA_SIZE, MAX_SERIALISED_HEADER_SIZE};
+pub use self::multipart_body::{BodyPart, MultipartBody, PartContent, MULTIPART_VERSION};
+pub use self::padding::{MIN_BODY_SIZE_CLASS, MIN_METADATA_SIZE_CLASS, PADDING_OVERHEAD};
+pub use self::replay_filter::{ReplayFilter, Seen};
+pub use self::signing::{Domain, PROTOCOL_VERSION, Signer, Verifier, signed_data};
 
 use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bincode::SizeLimit;
+use bincode::rustc_serialize::decode_from;
+use maidsafe_utilities::serialisation::{SerialisationError, serialise};
+use rustc_serialize::{Decodable, Encodable};
+use sodiumoxide::crypto::box_;
+
+// The length of a sealed box less that of its plaintext: an ephemeral public key and a MAC.
+const SEALED_BOX_OVERHEAD: usize = box_::PUBLICKEYBYTES + box_::MACBYTES;
+
+// The current time in seconds since the Unix epoch, as used for expiry times.
+fn seconds_since_epoch() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+// Deserialises `serialised`, which must not exceed `max_size` bytes and must be consumed exactly.
+// The decoder can't read beyond the end of the input, so no length prefix within it can cause more
+// than `max_size` bytes to be read.
+fn deserialise_bounded<T: Decodable>(serialised: &[u8], max_size: usize) -> Result<T, Error> {
+    if serialised.len() > max_size {
+        return Err(Error::InputTooLarge);
+    }
+    let mut reader = serialised;
+    let value = try!(decode_from(&mut reader, SizeLimit::Bounded(serialised.len() as u64))
+                         .map_err(SerialisationError::from));
+    if !reader.is_empty() {
+        return Err(Error::Malformed);
+    }
+    Ok(value)
+}
+
+// Serialises `value` prefixed with the `version` byte, as used by the versioned message body
+// encodings.  The result must not exceed `limit` bytes.
+fn serialise_versioned<T: Encodable>(version: u8,
+                                     value: &T,
+                                     limit: usize)
+                                     -> Result<Vec<u8>, Error> {
+    let mut serialised = vec![version];
+    serialised.extend_from_slice(&try!(serialise(value)));
+    if serialised.len() > limit {
+        return Err(Error::BodyTooLarge);
+    }
+    Ok(serialised)
+}
+
+// Deserialises the output of `serialise_versioned()`, which must not exceed `limit` bytes and must
+// start with the `version` byte.
+fn deserialise_versioned<T: Decodable>(version: u8,
+                                       serialised: &[u8],
+                                       limit: usize)
+                                       -> Result<T, Error> {
+    if serialised.len() > limit {
+        return Err(Error::InputTooLarge);
+    }
+    if serialised.first() != Some(&version) {
+        return Err(Error::Malformed);
+    }
+    deserialise_bounded(&serialised[1..], limit - 1)
+}
 
 // Format a vector of bytes as a hexadecimal number, ellipsising all but the first and last three.
 //
@@ -18,6 +18,19 @@
 /// Maximum allowed length for a [header's `metadata`](struct.MpidHeader.html#method.new) (128
 /// bytes).
 pub const MAX_HEADER_METADATA_SIZE: usize = 128;  // bytes
+/// Maximum allowed length of a serialised header (512 bytes), as accepted by
+/// [`MpidHeader::deserialise()`](struct.MpidHeader.html#method.deserialise).
+pub const MAX_SERIALISED_HEADER_SIZE: usize = 512;
+/// Maximum allowed length of the plaintext
+/// [metadata of an encrypted message](struct.MpidMessage.html#method.new_encrypted) (78 bytes).
+/// Encrypted metadata is always padded to this length, so its ciphertext occupies exactly
+/// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).
+pub const MAX_ENCRYPTED_METADATA_SIZE: usize = MAX_HEADER_METADATA_SIZE -
+                                               super::SEALED_BOX_OVERHEAD -
+                                               METADATA_LENGTH_SIZE;
+
+// Encrypted metadata is prefixed with its actual length as a little-endian u16.
+const METADATA_LENGTH_SIZE: usize = 2;
 
 use std::fmt::{self, Debug, Formatter};
 use std::sync::{Once, ONCE_INIT};
@@ -25,20 +38,96 @@ use std::sync::{Once, ONCE_INIT};
 use maidsafe_utilities::serialisation::serialise;
 use rand::{self, Rng};
 use sodiumoxide;
-use sodiumoxide::crypto::hash::sha512;
-use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
-use super::{Error, GUID_SIZE};
+use sodiumoxide::crypto::hash::{sha256, sha512};
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::sign::Signature;
+use super::{Codec, Error, GUID_SIZE, KeyResolver, MpidPublicId};
+use super::padding::SizeClasses;
+use super::signing::{self, Domain, Signer, Verifier};
 use xor_name::XorName;
 use messaging;
 
 static INITIALISE_SODIUMOXIDE: Once = ONCE_INIT;
 static mut sodiumoxide_init_result: bool = false;
 
+/// The urgency of a message, as indicated by its sender.  This is carried in the signed header so
+/// that a recipient can order its inbox without fetching the messages.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, RustcDecodable,
+         RustcEncodable)]
+pub enum Priority {
+    /// Lower than normal priority.
+    Low,
+    /// The default priority.
+    Normal,
+    /// Higher than normal priority.
+    High,
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority::Normal
+    }
+}
+
+/// Limits on the difference between a header's
+/// [creation timestamp](struct.MpidHeader.html#method.created) and the local clock, as applied by
+/// [`MpidHeader::validate_timestamp()`](struct.MpidHeader.html#method.validate_timestamp).
+///
+/// The default policy allows timestamps up to five minutes in the future and a day in the past.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct ClockSkewPolicy {
+    /// The maximum number of seconds by which a timestamp may be ahead of the local clock.
+    pub max_future_skew: u64,
+    /// The maximum age in seconds of a timestamp, or `None` to accept any past timestamp.
+    pub max_age: Option<u64>,
+}
+
+impl Default for ClockSkewPolicy {
+    fn default() -> ClockSkewPolicy {
+        ClockSkewPolicy {
+            max_future_skew: 5 * 60,
+            max_age: Some(24 * 60 * 60),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
-struct Detail {
-    sender: XorName,
-    guid: [u8; GUID_SIZE],
-    metadata: Vec<u8>,
+pub struct Detail {
+    pub sender: XorName,
+    pub guid: [u8; GUID_SIZE],
+    pub metadata: Vec<u8>,
+    pub message_hash: Option<sha256::Digest>,
+    pub expiry: Option<u64>,
+    pub priority: Priority,
+    pub reply_to: Option<XorName>,
+    pub encrypted_metadata: bool,
+    pub encrypted_body: bool,
+    pub created: u64,
+    pub codec: Codec,
+    pub padded: bool,
+    pub chunk_of: Option<[u8; GUID_SIZE]>,
+}
+
+impl Detail {
+    // Details with the given mandatory fields and all optional fields left unset.
+    pub fn new(sender: XorName, metadata: Vec<u8>, guid: [u8; GUID_SIZE], created: u64) -> Detail {
+        Detail {
+            sender: sender,
+            guid: guid,
+            metadata: metadata,
+            message_hash: None,
+            expiry: None,
+            priority: Priority::default(),
+            reply_to: None,
+            encrypted_metadata: false,
+            encrypted_body: false,
+            created: created,
+            codec: Codec::None,
+            padded: false,
+            chunk_of: None,
+        }
+    }
 }
 
 /// Minimal information about a given message which can be used as a notification to the receiver.
@@ -52,7 +141,8 @@ impl MpidHeader {
     /// Constructor.
     ///
     /// Each new `MpidHeader` will have a random unique identifier assigned to it, accessed via the
-    /// [`guid()`](#method.guid) getter.
+    /// [`guid()`](#method.guid) getter, and the current time as its creation timestamp, accessed
+    /// via the [`created()`](#method.created) getter.
     ///
     /// `sender` represents the name of the original creator of the message.
     ///
@@ -60,28 +150,48 @@ impl MpidHeader {
     /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).  It can be empty if
     /// desired.
     ///
-    /// `secret_key` will be used to generate a signature of `sender`, `guid` and `metadata`.
+    /// `signer` will be used to generate a signature of `sender`, `guid` and `metadata`.  A
+    /// sodiumoxide `SecretKey` can be passed directly; see [`Signer`](trait.Signer.html).
     ///
-    /// An error will be returned if `metadata` exceeds `MAX_HEADER_METADATA_SIZE` or if
-    /// serialisation during the signing process fails.
-    pub fn new(sender: XorName, metadata: Vec<u8>, secret_key: &SecretKey) -> Result<MpidHeader, Error> {
-        assert!(Self::initialise_sodiumoxide());
-        if metadata.len() > MAX_HEADER_METADATA_SIZE {
-            return Err(Error::MetadataTooLarge);
-        }
+    /// An error will be returned if `metadata` exceeds `MAX_HEADER_METADATA_SIZE`, if
+    /// serialisation during the signing process fails or if `signer` fails.
+    ///
+    /// A header constructed this way isn't bound to any message body; the headers of
+    /// [`MpidMessage`](struct.MpidMessage.html)s also commit to the message's recipient and body.
+    pub fn new<S: Signer + ?Sized>(sender: XorName,
+                                   metadata: Vec<u8>,
+                                   signer: &S)
+                                   -> Result<MpidHeader, Error> {
+        Self::new_with_rng(sender,
+                           metadata,
+                           &mut rand::thread_rng(),
+                           messaging::seconds_since_epoch(),
+                           signer)
+    }
 
-        let mut detail = Detail {
-            sender: sender,
-            guid: [0u8; GUID_SIZE],
-            metadata: metadata,
-        };
-        rand::thread_rng().fill_bytes(&mut detail.guid);
+    /// As per [`new()`](#method.new), except that the GUID is drawn from `rng` rather than the
+    /// thread-local generator and the caller supplies the creation timestamp `created`, in seconds
+    /// since the Unix epoch, so a seeded `rng` yields reproducible headers.
+    pub fn new_with_rng<R: Rng, S: Signer + ?Sized>(sender: XorName,
+                                                    metadata: Vec<u8>,
+                                                    rng: &mut R,
+                                                    created: u64,
+                                                    signer: &S)
+                                                    -> Result<MpidHeader, Error> {
+        Self::with_guid(sender, metadata, random_guid(rng), created, signer)
+    }
 
-        let encoded = try!(serialise(&detail));
-        Ok(MpidHeader {
-            detail: detail,
-            signature: sign::sign_detached(&encoded, secret_key),
-        })
+    /// As per [`new()`](#method.new), except that the caller supplies the GUID and the creation
+    /// timestamp `created`, in seconds since the Unix epoch.  Signing is deterministic, so
+    /// identical arguments always yield identical headers, e.g. when retrying a put.  The caller is
+    /// responsible for the GUID's uniqueness.
+    pub fn with_guid<S: Signer + ?Sized>(sender: XorName,
+                                         metadata: Vec<u8>,
+                                         guid: [u8; GUID_SIZE],
+                                         created: u64,
+                                         signer: &S)
+                                         -> Result<MpidHeader, Error> {
+        new_header(Detail::new(sender, metadata, guid, created), signer)
     }
 
     /// The name of the original creator of the message.
@@ -89,17 +199,146 @@ impl MpidHeader {
         &self.detail.sender
     }
 
-    /// A unique identifier generated randomly when calling `new()`.
+    /// A unique identifier generated randomly when calling `new()`, or supplied via
+    /// `with_guid()`.
     pub fn guid(&self) -> &[u8; GUID_SIZE] {
         &self.detail.guid
     }
 
-    /// Arbitrary, user-supplied information.
-    pub fn metadata(&self) -> &Vec<u8> {
+    /// Arbitrary, user-supplied information.  Any [padding](#method.is_padded) is removed, but this
+    /// is the ciphertext if the metadata is encrypted; use
+    /// [`open_metadata()`](#method.open_metadata) for that.
+    ///
+    /// An error will be returned if the padding is invalid.
+    pub fn metadata(&self) -> Result<Vec<u8>, Error> {
+        if self.detail.padded && !self.detail.encrypted_metadata {
+            SizeClasses::metadata().unpad(&self.detail.metadata)
+        } else {
+            Ok(self.detail.metadata.clone())
+        }
+    }
+
+    /// The metadata as signed and transmitted, i.e. after any padding and encryption.  Only this
+    /// form is subject to [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).
+    pub fn raw_metadata(&self) -> &Vec<u8> {
         &self.detail.metadata
     }
 
-    /// The signature of `sender`, `guid` and `metadata`, created when calling `new()`.
+    /// The SHA256 hash of the recipient and body of the message to which this header belongs, or
+    /// `None` if the header was constructed independently of a message.
+    pub fn message_hash(&self) -> Option<&sha256::Digest> {
+        self.detail.message_hash.as_ref()
+    }
+
+    /// The time, in seconds since the Unix epoch, after which the message may be discarded, or
+    /// `None` if it doesn't expire.  See [`MpidMessageBuilder`](struct.MpidMessageBuilder.html).
+    pub fn expiry(&self) -> Option<u64> {
+        self.detail.expiry
+    }
+
+    /// The time, in seconds since the Unix epoch, at which the header was created, as claimed by
+    /// its sender.
+    pub fn created(&self) -> u64 {
+        self.detail.created
+    }
+
+    /// Checks the creation timestamp against the local clock, where `now` is the local time in
+    /// seconds since the Unix epoch.  This allows recipients to reject replays of old headers.
+    ///
+    /// An error will be returned if the timestamp is further ahead of or behind `now` than allowed
+    /// by `policy`.
+    pub fn validate_timestamp(&self, now: u64, policy: &ClockSkewPolicy) -> Result<(), Error> {
+        let created = self.detail.created;
+        if created > now.saturating_add(policy.max_future_skew) {
+            return Err(Error::InvalidTimestamp);
+        }
+        if let Some(max_age) = policy.max_age {
+            if now > created.saturating_add(max_age) {
+                return Err(Error::InvalidTimestamp);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the message has expired by `now`, in seconds since the Unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.detail.expiry.map_or(false, |expiry| expiry <= now)
+    }
+
+    /// The priority of the message, `Priority::Normal` unless set by the sender.
+    pub fn priority(&self) -> Priority {
+        self.detail.priority
+    }
+
+    /// The name to which replies to the message should be addressed, if other than the sender.
+    pub fn reply_to(&self) -> Option<&XorName> {
+        self.detail.reply_to.as_ref()
+    }
+
+    /// The GUID of the manifest message if this is a part of a
+    /// [chunked message](struct.ChunkedMessage.html), so that recipients can group the parts with
+    /// their manifest.
+    pub fn chunk_of(&self) -> Option<&[u8; GUID_SIZE]> {
+        self.detail.chunk_of.as_ref()
+    }
+
+    /// Returns whether `metadata` is encrypted to the recipient of the message.  If so, it's always
+    /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html) bytes long, and can be
+    /// decrypted via [`open_metadata()`](#method.open_metadata).
+    pub fn is_metadata_encrypted(&self) -> bool {
+        self.detail.encrypted_metadata
+    }
+
+    /// Decrypts encrypted `metadata` using the recipient's encryption key pair and strips its
+    /// padding.
+    ///
+    /// An error will be returned if the metadata isn't encrypted or can't be decrypted with the
+    /// given keys, or if the padding is invalid.
+    pub fn open_metadata(&self,
+                         public_key: &box_::PublicKey,
+                         secret_key: &box_::SecretKey)
+                         -> Result<Vec<u8>, Error> {
+        if !self.detail.encrypted_metadata {
+            return Err(Error::DecryptionFailed);
+        }
+        let padded = try!(sealedbox::open(&self.detail.metadata, public_key, secret_key)
+                              .map_err(|()| Error::DecryptionFailed));
+        if padded.len() != MAX_ENCRYPTED_METADATA_SIZE + METADATA_LENGTH_SIZE {
+            return Err(Error::Malformed);
+        }
+        let length = padded[0] as usize | (padded[1] as usize) << 8;
+        let metadata = &padded[METADATA_LENGTH_SIZE..];
+        if length > MAX_ENCRYPTED_METADATA_SIZE ||
+           metadata[length..].iter().any(|&byte| byte != 0) {
+            return Err(Error::Malformed);
+        }
+        Ok(metadata[..length].to_vec())
+    }
+
+    /// Returns whether the body of the message is
+    /// [encrypted to its recipient](struct.MpidMessage.html#method.new_encrypted).
+    pub fn is_body_encrypted(&self) -> bool {
+        self.detail.encrypted_body
+    }
+
+    /// The codec with which the body of the message is compressed, before any encryption.
+    pub fn codec(&self) -> Codec {
+        self.detail.codec
+    }
+
+    /// Returns whether the metadata and body of the message are padded to hide their lengths.  See
+    /// [MpidMessageBuilder::pad()](struct.MpidMessageBuilder.html#method.pad).
+    pub fn is_padded(&self) -> bool {
+        self.detail.padded
+    }
+
+    /// Returns whether this header is bound to a message with the given `recipient` and `body`.
+    pub fn commits_to(&self, recipient: &XorName, body: &[u8]) -> bool {
+        self.detail.message_hash == Some(hash_message(recipient, body))
+    }
+
+    /// The signature of `sender`, `guid`, `metadata`, the message hash and the optional fields,
+    /// created when calling `new()`.
     pub fn signature(&self) -> &Signature {
         &self.signature
     }
@@ -111,14 +350,57 @@ impl MpidHeader {
         Ok(XorName(sha512::hash(&encoded[..]).0))
     }
 
-    /// Validates the header's signature against the provided `PublicKey`.
-    pub fn verify(&self, public_key: &PublicKey) -> bool {
+    /// Deserialises a header received from an untrusted source.
+    ///
+    /// Unlike deserialising via `RustcDecodable`, input longer than
+    /// [`MAX_SERIALISED_HEADER_SIZE`](constant.MAX_SERIALISED_HEADER_SIZE.html) is rejected before
+    /// decoding, and the decoded header is checked via [`validate()`](#method.validate).  The
+    /// signature isn't checked; use [`verify()`](#method.verify) for that.
+    pub fn deserialise(serialised: &[u8]) -> Result<MpidHeader, Error> {
+        let header: MpidHeader =
+            try!(messaging::deserialise_bounded(serialised, MAX_SERIALISED_HEADER_SIZE));
+        try!(header.validate());
+        Ok(header)
+    }
+
+    /// Checks the invariants enforced by [`new()`](#method.new), i.e. that `metadata` doesn't
+    /// exceed [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html), and that
+    /// encrypted metadata is exactly that length.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.detail.metadata.len() > MAX_HEADER_METADATA_SIZE {
+            return Err(Error::MetadataTooLarge);
+        }
+        if self.detail.encrypted_metadata &&
+           self.detail.metadata.len() != MAX_HEADER_METADATA_SIZE {
+            return Err(Error::Malformed);
+        }
+        Ok(())
+    }
+
+    /// Validates the header's signature via `verifier`, e.g. the sender's `PublicKey`.  Signatures
+    /// made over the header's details in any other [`Domain`](enum.Domain.html) are rejected.
+    pub fn verify<V: Verifier + ?Sized>(&self, verifier: &V) -> bool {
         match serialise(&self.detail) {
-            Ok(encoded) => sign::verify_detached(&self.signature, &encoded, public_key),
+            Ok(encoded) => {
+                signing::verify_detached(Domain::Header, &self.signature, &encoded, verifier)
+            }
             Err(_) => false,
         }
     }
 
+    /// Validates the header's signature against the signing key of `sender`, and that `sender` is
+    /// the header's [`sender()`](#method.sender).  As the name of an `MpidPublicId` is derived
+    /// from its keys, this needs no separate lookup of the sender's key.
+    pub fn verify_from_sender(&self, sender: &MpidPublicId) -> bool {
+        self.detail.sender == sender.name() && self.verify(sender)
+    }
+
+    /// Validates the header's signature against the keys of its sender, as looked up via
+    /// `resolver`.  Returns `true` if any of the keys verifies the signature.
+    pub fn verify_with_resolver<K: KeyResolver + ?Sized>(&self, resolver: &K) -> bool {
+        resolver.resolve(&self.detail.sender).iter().any(|public_key| self.verify(public_key))
+    }
+
     #[allow(unsafe_code)]
     fn initialise_sodiumoxide() -> bool {
         unsafe {
@@ -133,18 +415,75 @@ impl MpidHeader {
 impl Debug for MpidHeader {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
         write!(formatter,
-               "MpidHeader {{ sender: {:?}, guid: {}, metadata: {}, signature: {} }}",
+               "MpidHeader {{ sender: {:?}, guid: {}, metadata: {}, message_hash: {}, expiry: \
+                {:?}, priority: {:?}, reply_to: {:?}, encrypted_metadata: {}, encrypted_body: {}, \
+                created: {:?}, codec: {:?}, padded: {}, chunk_of: {}, signature: {} }}",
                self.detail.sender,
                messaging::format_binary_array(&self.detail.guid),
                messaging::format_binary_array(&self.detail.metadata),
+               self.detail
+                   .message_hash
+                   .map_or_else(|| "None".to_owned(),
+                                |hash| messaging::format_binary_array(&hash.0)),
+               self.detail.expiry,
+               self.detail.priority,
+               self.detail.reply_to,
+               self.detail.encrypted_metadata,
+               self.detail.encrypted_body,
+               self.detail.created,
+               self.detail.codec,
+               self.detail.padded,
+               self.detail
+                   .chunk_of
+                   .map_or_else(|| "None".to_owned(), messaging::format_binary_array),
                messaging::format_binary_array(&self.signature))
     }
 }
 
+// Constructs a signed header from `detail`, which may bind it to a message via `message_hash`.
+pub fn new_header<S: Signer + ?Sized>(detail: Detail, signer: &S) -> Result<MpidHeader, Error> {
+    assert!(MpidHeader::initialise_sodiumoxide());
+    if detail.metadata.len() > MAX_HEADER_METADATA_SIZE {
+        return Err(Error::MetadataTooLarge);
+    }
+
+    let encoded = try!(serialise(&detail));
+    let signature = try!(signing::sign_detached(Domain::Header, &encoded, signer));
+    Ok(MpidHeader {
+        detail: detail,
+        signature: signature,
+    })
+}
+
+// Pads `metadata`, which must not exceed `MAX_ENCRYPTED_METADATA_SIZE`, to that length and
+// encrypts it such that it can only be decrypted with the secret key matching `public_key`.
+pub fn seal_metadata(metadata: &[u8], public_key: &box_::PublicKey) -> Vec<u8> {
+    let mut padded = vec![0u8; METADATA_LENGTH_SIZE + MAX_ENCRYPTED_METADATA_SIZE];
+    padded[0] = metadata.len() as u8;
+    padded[1] = (metadata.len() >> 8) as u8;
+    padded[METADATA_LENGTH_SIZE..METADATA_LENGTH_SIZE + metadata.len()].copy_from_slice(metadata);
+    sealedbox::seal(&padded, public_key)
+}
+
+pub fn random_guid<R: Rng>(rng: &mut R) -> [u8; GUID_SIZE] {
+    let mut guid = [0u8; GUID_SIZE];
+    rng.fill_bytes(&mut guid);
+    guid
+}
+
+// The hash committed to by the header of a message with the given `recipient` and `body`.  The
+// recipient's name has a fixed length, so the concatenation is unambiguous.
+pub fn hash_message(recipient: &XorName, body: &[u8]) -> sha256::Digest {
+    let mut input = Vec::with_capacity(recipient.0.len() + body.len());
+    input.extend_from_slice(&recipient.0);
+    input.extend_from_slice(body);
+    sha256::hash(&input)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use rand;
+    use rand::{self, SeedableRng, XorShiftRng};
     use sodiumoxide::crypto::sign;
     use xor_name::XorName;
     use messaging;
@@ -157,11 +496,11 @@ mod test {
         // Check with metadata which is empty, then at size limit, then just above limit.
         {
             let header = unwrap_result!(MpidHeader::new(sender.clone(), vec![], &secret_key));
-            assert!(header.metadata().is_empty());
+            assert!(unwrap_result!(header.metadata()).is_empty());
         }
         let mut metadata = messaging::generate_random_bytes(MAX_HEADER_METADATA_SIZE);
         let header = unwrap_result!(MpidHeader::new(sender.clone(), metadata.clone(), &secret_key));
-        assert!(*header.metadata() == metadata);
+        assert!(unwrap_result!(header.metadata()) == metadata);
         metadata.push(0);
         assert!(MpidHeader::new(sender.clone(), metadata.clone(), &secret_key).is_err());
         let _ = metadata.pop();
@@ -182,12 +521,77 @@ mod test {
         assert!(header1 != header2);
         assert_eq!(*header1.sender(), sender);
         assert_eq!(header1.sender(), header2.sender());
-        assert_eq!(*header1.metadata(), metadata);
-        assert_eq!(header1.metadata(), header2.metadata());
+        assert_eq!(unwrap_result!(header1.metadata()), metadata);
+        assert_eq!(header1.raw_metadata(), header2.raw_metadata());
         assert!(header1.guid() != header2.guid());
         assert!(header1.signature() != header2.signature());
         let name1 = unwrap_result!(header1.name());
         let name2 = unwrap_result!(header2.name());
         assert!(name1 != name2);
+
+        // Check that headers with the same GUID, or from identically-seeded generators, and the
+        // same timestamp are identical.
+        let header3 = unwrap_result!(MpidHeader::with_guid(sender.clone(),
+                                                           metadata.clone(),
+                                                           *header1.guid(),
+                                                           header1.created(),
+                                                           &secret_key));
+        assert_eq!(header3,
+                   unwrap_result!(MpidHeader::with_guid(sender.clone(),
+                                                        metadata.clone(),
+                                                        *header1.guid(),
+                                                        header1.created(),
+                                                        &secret_key)));
+        assert_eq!(header3, header1);
+        let seed = [1, 2, 3, 4];
+        let header4 = unwrap_result!(MpidHeader::new_with_rng(sender.clone(),
+                                                              metadata.clone(),
+                                                              &mut XorShiftRng::from_seed(seed),
+                                                              1,
+                                                              &secret_key));
+        let header5 = unwrap_result!(MpidHeader::new_with_rng(sender.clone(),
+                                                              metadata.clone(),
+                                                              &mut XorShiftRng::from_seed(seed),
+                                                              1,
+                                                              &secret_key));
+        assert_eq!(header4, header5);
+        assert_eq!(unwrap_result!(header4.name()), unwrap_result!(header5.name()));
+    }
+
+    #[test]
+    fn timestamp() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let before = messaging::seconds_since_epoch();
+        let header = unwrap_result!(MpidHeader::new(sender.clone(), vec![], &secret_key));
+        let after = messaging::seconds_since_epoch();
+        let created = header.created();
+        assert!(before <= created && created <= after);
+
+        let policy = ClockSkewPolicy {
+            max_future_skew: 10,
+            max_age: Some(100),
+        };
+        unwrap_result!(header.validate_timestamp(created, &policy));
+        unwrap_result!(header.validate_timestamp(created - 10, &policy));
+        unwrap_result!(header.validate_timestamp(created + 100, &policy));
+        assert!(header.validate_timestamp(created - 11, &policy).is_err());
+        assert!(header.validate_timestamp(created + 101, &policy).is_err());
+        let lenient = ClockSkewPolicy {
+            max_future_skew: 10,
+            max_age: None,
+        };
+        unwrap_result!(header.validate_timestamp(u64::max_value(), &lenient));
+        unwrap_result!(header.validate_timestamp(created, &ClockSkewPolicy::default()));
+
+        // Headers with a supplied timestamp are validated likewise.
+        let header = unwrap_result!(MpidHeader::with_guid(sender,
+                                                          vec![],
+                                                          [0; GUID_SIZE],
+                                                          1,
+                                                          &secret_key));
+        assert_eq!(header.created(), 1);
+        unwrap_result!(header.validate_timestamp(created, &lenient));
+        assert!(header.validate_timestamp(created, &policy).is_err());
     }
 }
@@ -0,0 +1,1178 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashSet;
+
+use client_errors::MessagingError;
+use sodiumoxide::crypto::sign::PublicKey;
+use super::{ClockSkewPolicy, Error, Inbox, KeyResolver, MAX_HEADER_PAGE_SIZE, MailboxEntry,
+            MailboxStore, MemoryMailboxStore, MpidHeader, MpidMessage, MpidMessageWrapper, Outbox,
+            ReplayFilter, Seen};
+use xor_name::XorName;
+
+// The maximum number of `(sender, guid)` pairs remembered per sender at a time.
+const REPLAY_FILTER_CAPACITY: usize = 10_000;
+// The maximum number of `(sender, guid)` pairs remembered in total at a time.
+const REPLAY_FILTER_TOTAL_CAPACITY: usize = 100_000;
+
+type FailureResponse = fn(XorName, MessagingError) -> MpidMessageWrapper;
+
+/// An outcome of [`MpidManager::handle()`](struct.MpidManager.html#method.handle).
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Action {
+    /// `wrapper` should be sent to `dst`.  Where `dst` is the name of the account's owner, the
+    /// recipient is the owner's Client; otherwise it is the MpidManagers of `dst`.
+    Send {
+        /// Name of the destination.
+        dst: XorName,
+        /// Wrapper to be sent.
+        wrapper: MpidMessageWrapper,
+    },
+    /// The named message has been added to the outbox.
+    OutboxAdded(XorName),
+    /// The named message has been removed from the outbox.
+    OutboxRemoved(XorName),
+    /// The named header has been added to the inbox.
+    InboxAdded(XorName),
+    /// The named header has been removed from the inbox.
+    InboxRemoved(XorName),
+}
+
+/// Transport-agnostic state machine for the MpidManagers of a single account.
+///
+/// It holds the account's inbox (headers of messages sent to the owner) and outbox (messages sent
+/// by the owner which haven't yet been deleted).  Each incoming `MpidMessageWrapper` is passed to
+/// [`handle()`](#method.handle) along with the name of its source; where the source is the owner,
+/// the wrapper is treated as coming from the owner's Client, otherwise it is treated as coming
+/// from the MpidManagers of the named account.
+///
+/// Headers and messages passed on by other accounts' MpidManagers are verified against their
+/// senders' keys, as looked up via the manager's [`KeyResolver`](trait.KeyResolver.html).
+///
+/// Every change to the inbox and outbox is written through to the manager's
+/// [`MailboxStore`](trait.MailboxStore.html) before taking effect, so a manager constructed via
+/// [`with_store()`](#method.with_store) over e.g. a
+/// [`FileMailboxStore`](struct.FileMailboxStore.html) resumes with the same inbox and outbox.
+pub struct MpidManager<R: KeyResolver, S: MailboxStore = MemoryMailboxStore> {
+    owner: XorName,
+    public_key: PublicKey,
+    resolver: R,
+    store: S,
+    inbox: Inbox,
+    outbox: Outbox,
+    blocked_senders: HashSet<XorName>,
+    clock_skew_policy: ClockSkewPolicy,
+    replay_filter: ReplayFilter,
+}
+
+impl<R: KeyResolver> MpidManager<R> {
+    /// Constructor.  `public_key` is used to verify messages put by the owner's Client, and
+    /// `resolver` to look up the keys of senders of messages to the owner.  The inbox and outbox
+    /// are only held in memory.
+    pub fn new(owner: XorName, public_key: PublicKey, resolver: R) -> MpidManager<R> {
+        MpidManager::empty(owner, public_key, resolver, MemoryMailboxStore::new())
+    }
+}
+
+impl<R: KeyResolver, S: MailboxStore> MpidManager<R, S> {
+    /// Constructs a manager whose inbox and outbox are persisted in `store`, starting with the
+    /// entries already held there for `owner`.  Otherwise as per [`new()`](#method.new).
+    ///
+    /// An error will be returned if the store fails or its entries exceed the inbox or outbox
+    /// limits.
+    pub fn with_store(owner: XorName,
+                      public_key: PublicKey,
+                      resolver: R,
+                      store: S)
+                      -> Result<MpidManager<R, S>, Error> {
+        let mut manager = MpidManager::empty(owner, public_key, resolver, store);
+        for name in try!(manager.store.list(&manager.owner)) {
+            match try!(manager.store.get(&manager.owner, &name)) {
+                Some(MailboxEntry::Header(header)) => {
+                    let _ = try!(manager.inbox.insert(header));
+                }
+                Some(MailboxEntry::Message(message)) => {
+                    let _ = try!(manager.outbox.insert(message));
+                }
+                None => (),
+            }
+        }
+        Ok(manager)
+    }
+
+    fn empty(owner: XorName, public_key: PublicKey, resolver: R, store: S) -> MpidManager<R, S> {
+        MpidManager {
+            owner: owner,
+            public_key: public_key,
+            resolver: resolver,
+            store: store,
+            inbox: Inbox::new(),
+            outbox: Outbox::new(),
+            blocked_senders: HashSet::new(),
+            clock_skew_policy: ClockSkewPolicy::default(),
+            replay_filter: ReplayFilter::new(replay_window(&ClockSkewPolicy::default()),
+                                             REPLAY_FILTER_CAPACITY,
+                                             REPLAY_FILTER_TOTAL_CAPACITY),
+        }
+    }
+
+    /// The name of the account's owner.
+    pub fn owner(&self) -> &XorName {
+        &self.owner
+    }
+
+    /// The resolver used to look up the keys of senders of messages to the owner.
+    pub fn resolver(&self) -> &R {
+        &self.resolver
+    }
+
+    /// The store in which the inbox and outbox are persisted.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The store in which the inbox and outbox are persisted, e.g. for compacting it.  Its entries
+    /// for the owner mustn't be modified other than via the manager.
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
+    }
+
+    /// The account's inbox.
+    pub fn inbox(&self) -> &Inbox {
+        &self.inbox
+    }
+
+    /// The account's outbox.
+    pub fn outbox(&self) -> &Outbox {
+        &self.outbox
+    }
+
+    /// The policy against which the creation timestamps of put headers and messages are checked.
+    pub fn clock_skew_policy(&self) -> &ClockSkewPolicy {
+        &self.clock_skew_policy
+    }
+
+    /// Sets the policy against which the creation timestamps of put headers and messages are
+    /// checked, `ClockSkewPolicy::default()` unless set.  Accepted headers are remembered for as
+    /// long as the policy accepts their timestamps.
+    pub fn set_clock_skew_policy(&mut self, policy: ClockSkewPolicy) {
+        self.replay_filter.set_window(replay_window(&policy));
+        self.clock_skew_policy = policy;
+    }
+
+    /// Rejects any further headers sent by `sender`, returning `false` if already blocked.
+    pub fn block_sender(&mut self, sender: XorName) -> bool {
+        self.blocked_senders.insert(sender)
+    }
+
+    /// Accepts headers sent by `sender` again, returning `false` if it wasn't blocked.
+    pub fn unblock_sender(&mut self, sender: &XorName) -> bool {
+        self.blocked_senders.remove(sender)
+    }
+
+    /// Handles `wrapper` received from `src` at time `now`, in seconds since the Unix epoch,
+    /// returning the resulting actions.
+    ///
+    /// An error occurs if the request is invalid for the given source, if a signature fails to
+    /// verify, if a put header or message has already expired by `now` or its creation timestamp
+    /// violates the manager's [`ClockSkewPolicy`](struct.ClockSkewPolicy.html), if a referenced
+    /// message doesn't exist, if the sender is blocked or if storing the
+    /// request would exceed [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html) or
+    /// [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html).  The state is left unchanged on error.
+    ///
+    /// Where the failed request is a `PutMessage`, `GetMessage` or `DeleteMessage` from the Client,
+    /// or a `PutHeader` or `GetMessage` from another account's MpidManagers, the error is sent back
+    /// to `src` as the corresponding failure response.  Otherwise it is returned as an `Err`.
+    ///
+    /// The sender and GUID of each accepted `PutMessage` from the Client and `PutHeader` from
+    /// another account's MpidManagers are remembered for as long as the `ClockSkewPolicy` accepts
+    /// their timestamps, so that retries and replays of these are ignored even after the message
+    /// has been deleted; older replays fail timestamp validation.  A different message reusing a
+    /// remembered sender and GUID is rejected as invalid.
+    pub fn handle(&mut self,
+                  src: &XorName,
+                  wrapper: MpidMessageWrapper,
+                  now: u64)
+                  -> Result<Vec<Action>, Error> {
+        let failure_response = try!(self.failure_response(src, &wrapper));
+        let result = if *src == self.owner {
+            self.handle_client_request(wrapper, now)
+        } else {
+            self.handle_manager_request(src, wrapper, now)
+        };
+        match (result, failure_response) {
+            (Err(error), Some((response, name))) => {
+                Ok(vec![Action::Send {
+                            dst: src.clone(),
+                            wrapper: response(name, MessagingError::from(error)),
+                        }])
+            }
+            (result, _) => result,
+        }
+    }
+
+    // Returns the constructor and message name for the response to `wrapper` if it fails.
+    fn failure_response(&self,
+                        src: &XorName,
+                        wrapper: &MpidMessageWrapper)
+                        -> Result<Option<(FailureResponse, XorName)>, Error> {
+        let from_owner = *src == self.owner;
+        let (response, name): (FailureResponse, XorName) = match *wrapper {
+            MpidMessageWrapper::PutMessage(ref message) if from_owner => {
+                (MpidMessageWrapper::PutMessageFailure, try!(message.name()))
+            }
+            MpidMessageWrapper::PutHeader(ref header) if !from_owner => {
+                (MpidMessageWrapper::PutMessageFailure, try!(header.name()))
+            }
+            MpidMessageWrapper::GetMessage(ref header) => {
+                (MpidMessageWrapper::GetMessageFailure, try!(header.name()))
+            }
+            MpidMessageWrapper::DeleteMessage(ref name) if from_owner => {
+                (MpidMessageWrapper::DeleteMessageFailure, name.clone())
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some((response, name)))
+    }
+
+    fn handle_client_request(&mut self,
+                             wrapper: MpidMessageWrapper,
+                             now: u64)
+                             -> Result<Vec<Action>, Error> {
+        match wrapper {
+            MpidMessageWrapper::Online => {
+                Ok(self.inbox
+                       .headers()
+                       .map(|header| {
+                           self.send_to_owner(MpidMessageWrapper::PutHeader(header.clone()))
+                       })
+                       .collect())
+            }
+            MpidMessageWrapper::PutMessage(message) => self.put_outbox_message(message, now),
+            MpidMessageWrapper::GetMessage(header) => {
+                let name = try!(header.name());
+                if !self.inbox.contains(&name) {
+                    return Err(Error::NoSuchMessage);
+                }
+                Ok(vec![Action::Send {
+                            dst: header.sender().clone(),
+                            wrapper: MpidMessageWrapper::GetMessage(header),
+                        }])
+            }
+            MpidMessageWrapper::OutboxHas(names) => {
+                let headers = names.iter()
+                                   .filter_map(|name| self.outbox.get(name))
+                                   .map(|message| message.header().clone())
+                                   .collect();
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::OutboxHasResponse(headers))])
+            }
+            MpidMessageWrapper::GetOutboxHeaders => {
+                let headers = self.outbox.headers_page(None, MAX_HEADER_PAGE_SIZE).headers;
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::GetOutboxHeadersResponse(headers))])
+            }
+            MpidMessageWrapper::InboxHas(names) => {
+                let headers = names.iter()
+                                   .filter_map(|name| self.inbox.get(name))
+                                   .cloned()
+                                   .collect();
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::InboxHasResponse(headers))])
+            }
+            MpidMessageWrapper::GetInboxHeaders => {
+                let headers = self.inbox.headers_page(None, MAX_HEADER_PAGE_SIZE).headers;
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::GetInboxHeadersResponse(headers))])
+            }
+            MpidMessageWrapper::GetOutboxHeadersPage(start_after, page_size) => {
+                let page = self.outbox.headers_page(start_after.as_ref(), page_size as usize);
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::GetOutboxHeadersPageResponse(page))])
+            }
+            MpidMessageWrapper::GetInboxHeadersPage(start_after, page_size) => {
+                let page = self.inbox.headers_page(start_after.as_ref(), page_size as usize);
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::GetInboxHeadersPageResponse(page))])
+            }
+            MpidMessageWrapper::DeleteMessage(name) => {
+                if try!(self.remove_message(&name)).is_some() {
+                    return Ok(vec![Action::OutboxRemoved(name)]);
+                }
+                match try!(self.remove_header(&name)) {
+                    Some(header) => {
+                        Ok(vec![Action::InboxRemoved(name.clone()),
+                                Action::Send {
+                                    dst: header.sender().clone(),
+                                    wrapper: MpidMessageWrapper::DeleteHeader(name),
+                                }])
+                    }
+                    None => Err(Error::NoSuchMessage),
+                }
+            }
+            MpidMessageWrapper::PutHeader(_) |
+            MpidMessageWrapper::OutboxHasResponse(_) |
+            MpidMessageWrapper::GetOutboxHeadersResponse(_) |
+            MpidMessageWrapper::DeleteHeader(_) |
+            MpidMessageWrapper::InboxHasResponse(_) |
+            MpidMessageWrapper::GetInboxHeadersResponse(_) |
+            MpidMessageWrapper::GetOutboxHeadersPageResponse(_) |
+            MpidMessageWrapper::GetInboxHeadersPageResponse(_) |
+            MpidMessageWrapper::PutMessageFailure(..) |
+            MpidMessageWrapper::GetMessageFailure(..) |
+            MpidMessageWrapper::DeleteMessageFailure(..) |
+            MpidMessageWrapper::MessageExpired(_) => Err(Error::InvalidRequest),
+        }
+    }
+
+    fn handle_manager_request(&mut self,
+                              src: &XorName,
+                              wrapper: MpidMessageWrapper,
+                              now: u64)
+                              -> Result<Vec<Action>, Error> {
+        match wrapper {
+            // A notification of a new message for the owner from the sender's managers.
+            MpidMessageWrapper::PutHeader(header) => {
+                if header.sender() != src {
+                    return Err(Error::InvalidRequest);
+                }
+                try!(header.validate());
+                if !header.verify_with_resolver(&self.resolver) {
+                    return Err(Error::InvalidSignature);
+                }
+                if header.is_expired(now) {
+                    return Err(Error::Expired);
+                }
+                try!(header.validate_timestamp(now, &self.clock_skew_policy));
+                if self.blocked_senders.contains(src) {
+                    return Err(Error::SenderBlocked);
+                }
+                if try!(self.is_replay(&header, now)) {
+                    return Ok(vec![]);
+                }
+                let name = try!(header.name());
+                if !try!(self.insert_header(header.clone())) {
+                    return Ok(vec![]);
+                }
+                if let Err(error) = self.replay_filter.insert(&header, now) {
+                    let _ = try!(self.remove_header(&name));
+                    return Err(error);
+                }
+                Ok(vec![Action::InboxAdded(name),
+                        self.send_to_owner(MpidMessageWrapper::PutHeader(header))])
+            }
+            // A request from the recipient's managers for a message in the owner's outbox.
+            MpidMessageWrapper::GetMessage(header) => {
+                let name = try!(header.name());
+                match self.outbox.get(&name) {
+                    Some(message) if message.recipient() == src => {
+                        Ok(vec![Action::Send {
+                                    dst: src.clone(),
+                                    wrapper: MpidMessageWrapper::PutMessage(message.clone()),
+                                }])
+                    }
+                    _ => Err(Error::NoSuchMessage),
+                }
+            }
+            // A message retrieved from the sender's managers, to be passed on to the owner.
+            MpidMessageWrapper::PutMessage(message) => {
+                if message.header().sender() != src || *message.recipient() != self.owner {
+                    return Err(Error::InvalidRequest);
+                }
+                if !message.verify_with_resolver(&self.resolver) {
+                    return Err(Error::InvalidSignature);
+                }
+                if !self.inbox.contains(&try!(message.name())) {
+                    return Err(Error::NoSuchMessage);
+                }
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::PutMessage(message))])
+            }
+            // The recipient of a message in the owner's outbox no longer needs it.
+            MpidMessageWrapper::DeleteHeader(name) => {
+                try!(self.remove_outbox_message(src, &name));
+                Ok(vec![Action::OutboxRemoved(name)])
+            }
+            // The recipient's managers couldn't store the header of a message in the owner's
+            // outbox, so the message is dropped and the owner informed.
+            MpidMessageWrapper::PutMessageFailure(name, error) => {
+                try!(self.remove_outbox_message(src, &name));
+                Ok(vec![Action::OutboxRemoved(name.clone()),
+                        self.send_to_owner(MpidMessageWrapper::PutMessageFailure(name, error))])
+            }
+            // The sender's managers couldn't provide a message requested by the owner.
+            MpidMessageWrapper::GetMessageFailure(name, error) => {
+                match self.inbox.get(&name) {
+                    Some(header) if header.sender() == src => (),
+                    _ => return Err(Error::NoSuchMessage),
+                }
+                Ok(vec![self.send_to_owner(MpidMessageWrapper::GetMessageFailure(name, error))])
+            }
+            // The counterpart of a message in the owner's inbox or outbox has expired.  The message
+            // is only purged if it has also expired by the local clock, as only its signed expiry
+            // time can be trusted; otherwise it's left to `remove_expired()`.
+            MpidMessageWrapper::MessageExpired(name) => {
+                let outbox_expired = match self.outbox.get(&name) {
+                    Some(message) => message.recipient() == src && message.header().is_expired(now),
+                    None => false,
+                };
+                if outbox_expired {
+                    let _ = try!(self.remove_message(&name));
+                    return Ok(vec![Action::OutboxRemoved(name)]);
+                }
+                let inbox_expired = match self.inbox.get(&name) {
+                    Some(header) => header.sender() == src && header.is_expired(now),
+                    None => false,
+                };
+                if inbox_expired {
+                    let _ = try!(self.remove_header(&name));
+                    return Ok(vec![Action::InboxRemoved(name)]);
+                }
+                Ok(vec![])
+            }
+            MpidMessageWrapper::Online |
+            MpidMessageWrapper::OutboxHas(_) |
+            MpidMessageWrapper::OutboxHasResponse(_) |
+            MpidMessageWrapper::GetOutboxHeaders |
+            MpidMessageWrapper::GetOutboxHeadersResponse(_) |
+            MpidMessageWrapper::DeleteMessage(_) |
+            MpidMessageWrapper::InboxHas(_) |
+            MpidMessageWrapper::InboxHasResponse(_) |
+            MpidMessageWrapper::GetInboxHeaders |
+            MpidMessageWrapper::GetInboxHeadersResponse(_) |
+            MpidMessageWrapper::GetOutboxHeadersPage(..) |
+            MpidMessageWrapper::GetOutboxHeadersPageResponse(_) |
+            MpidMessageWrapper::GetInboxHeadersPage(..) |
+            MpidMessageWrapper::GetInboxHeadersPageResponse(_) |
+            MpidMessageWrapper::DeleteMessageFailure(..) => Err(Error::InvalidRequest),
+        }
+    }
+
+    /// Removes every header and message which has expired by `now`, in seconds since the Unix
+    /// epoch, returning the resulting actions.  The MpidManagers holding each purged message's
+    /// counterpart are sent a `MessageExpired` so that they purge it too.
+    ///
+    /// This should be called periodically, as expired messages are otherwise only purged when
+    /// their counterparts expire.  An error is returned if the store fails, in which case entries
+    /// purged before the failure remain purged.
+    pub fn remove_expired(&mut self, now: u64) -> Result<Vec<Action>, Error> {
+        let mut actions = vec![];
+        for name in self.outbox.expired(now) {
+            if let Some(message) = try!(self.remove_message(&name)) {
+                actions.push(Action::OutboxRemoved(name.clone()));
+                actions.push(Action::Send {
+                    dst: message.recipient().clone(),
+                    wrapper: MpidMessageWrapper::MessageExpired(name),
+                });
+            }
+        }
+        for name in self.inbox.expired(now) {
+            if let Some(header) = try!(self.remove_header(&name)) {
+                actions.push(Action::InboxRemoved(name.clone()));
+                actions.push(Action::Send {
+                    dst: header.sender().clone(),
+                    wrapper: MpidMessageWrapper::MessageExpired(name),
+                });
+            }
+        }
+        Ok(actions)
+    }
+
+    // Removes the named message from the outbox if `recipient` is its recipient.
+    fn remove_outbox_message(&mut self, recipient: &XorName, name: &XorName) -> Result<(), Error> {
+        match self.outbox.get(name) {
+            Some(message) if message.recipient() == recipient => (),
+            _ => return Err(Error::NoSuchMessage),
+        }
+        let _ = try!(self.remove_message(name));
+        Ok(())
+    }
+
+    // Adds `header` to the inbox and the store, returning `false` if it's already held.
+    fn insert_header(&mut self, header: MpidHeader) -> Result<bool, Error> {
+        let name = try!(header.name());
+        if !try!(self.inbox.insert(header.clone())) {
+            return Ok(false);
+        }
+        if let Err(error) = self.store.put(&self.owner, &name, MailboxEntry::Header(header)) {
+            let _ = self.inbox.remove(&name);
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    // Adds `message` to the outbox and the store, returning `false` if it's already held.
+    fn insert_message(&mut self, message: MpidMessage) -> Result<bool, Error> {
+        let name = try!(message.name());
+        if !try!(self.outbox.insert(message.clone())) {
+            return Ok(false);
+        }
+        if let Err(error) = self.store.put(&self.owner, &name, MailboxEntry::Message(message)) {
+            let _ = self.outbox.remove(&name);
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    // Removes the named header from the store and the inbox.
+    fn remove_header(&mut self, name: &XorName) -> Result<Option<MpidHeader>, Error> {
+        if !self.inbox.contains(name) {
+            return Ok(None);
+        }
+        let _ = try!(self.store.delete(&self.owner, name));
+        Ok(self.inbox.remove(name))
+    }
+
+    // Removes the named message from the store and the outbox.
+    fn remove_message(&mut self, name: &XorName) -> Result<Option<MpidMessage>, Error> {
+        if !self.outbox.contains(name) {
+            return Ok(None);
+        }
+        let _ = try!(self.store.delete(&self.owner, name));
+        Ok(self.outbox.remove(name))
+    }
+
+    fn put_outbox_message(&mut self, message: MpidMessage, now: u64) -> Result<Vec<Action>, Error> {
+        if *message.header().sender() != self.owner {
+            return Err(Error::InvalidRequest);
+        }
+        try!(message.validate());
+        if !message.verify(&self.public_key) {
+            return Err(Error::InvalidSignature);
+        }
+        if message.header().is_expired(now) {
+            return Err(Error::Expired);
+        }
+        try!(message.header().validate_timestamp(now, &self.clock_skew_policy));
+        if try!(self.is_replay(message.header(), now)) {
+            return Ok(vec![]);
+        }
+        let name = try!(message.name());
+        let actions = vec![Action::OutboxAdded(name),
+                           Action::Send {
+                               dst: message.recipient().clone(),
+                               wrapper: MpidMessageWrapper::PutHeader(message.header().clone()),
+                           }];
+        let header = message.header().clone();
+        if !try!(self.insert_message(message)) {
+            return Ok(vec![]);
+        }
+        if let Err(error) = self.replay_filter.insert(&header, now) {
+            let _ = try!(self.remove_message(&name));
+            return Err(error);
+        }
+        Ok(actions)
+    }
+
+    // Returns whether `header` has already been accepted, in which case the put is a retry or
+    // replay to be ignored.  A different header reusing the sender and GUID of an accepted one is
+    // rejected.
+    fn is_replay(&self, header: &MpidHeader, now: u64) -> Result<bool, Error> {
+        match try!(self.replay_filter.check(header, now)) {
+            Seen::New => Ok(false),
+            Seen::Duplicate => Ok(true),
+            Seen::Conflict => Err(Error::InvalidRequest),
+        }
+    }
+
+    fn send_to_owner(&self, wrapper: MpidMessageWrapper) -> Action {
+        Action::Send {
+            dst: self.owner.clone(),
+            wrapper: wrapper,
+        }
+    }
+}
+
+// How long, in seconds, the `(sender, guid)` pairs of accepted headers are remembered under
+// `policy`: its maximum age plus future skew, so that replays arriving after they're forgotten fail
+// timestamp validation.
+fn replay_window(policy: &ClockSkewPolicy) -> u64 {
+    policy.max_age.map_or(u64::max_value(),
+                          |max_age| max_age.saturating_add(policy.max_future_skew))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use rand;
+    use client_errors::MessagingError;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use maidsafe_utilities::serialisation::serialise;
+    use messaging::{self, ClockSkewPolicy, Error, FileMailboxStore, GUID_SIZE, MAX_HEADER_PAGE_SIZE,
+                    MemoryKeyResolver, MpidMessage, MpidMessageWrapper};
+    use messaging::{mpid_header, mpid_message};
+    use sodiumoxide::crypto::sign::SecretKey;
+
+    // Constructs a message with the given expiry time, which unlike `MpidMessageBuilder` allows
+    // for times in the past.
+    fn expiring_message(sender: &XorName,
+                        recipient: &XorName,
+                        expiry: Option<u64>,
+                        secret_key: &SecretKey)
+                        -> MpidMessage {
+        let guid = mpid_header::random_guid(&mut rand::thread_rng());
+        let created = messaging::seconds_since_epoch();
+        let mut detail = mpid_header::Detail::new(sender.clone(), vec![], guid, created);
+        detail.expiry = expiry;
+        unwrap_result!(mpid_message::new_message(detail, recipient.clone(), vec![], secret_key))
+    }
+
+    #[test]
+    fn full() {
+        let now = messaging::seconds_since_epoch();
+        let (sender_public_key, sender_secret_key) = sign::gen_keypair();
+        let (recipient_public_key, _) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender.clone(), sender_public_key);
+        let mut sender_manager = MpidManager::new(sender.clone(), sender_public_key, &resolver);
+        let mut recipient_manager = MpidManager::new(recipient.clone(),
+                                                     recipient_public_key,
+                                                     &resolver);
+
+        let message = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                      messaging::generate_random_bytes(10),
+                                                      recipient.clone(),
+                                                      messaging::generate_random_bytes(100),
+                                                      &sender_secret_key));
+        let name = unwrap_result!(message.name());
+        let header = message.header().clone();
+
+        // Sender's Client puts the message; the header is sent on to the recipient's managers.
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let actions = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::OutboxAdded(name.clone()),
+                        Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: MpidMessageWrapper::PutHeader(header.clone()),
+                        }]);
+        assert_eq!(sender_manager.outbox().get(&name), Some(&message));
+
+        // Recipient's managers store the header and notify the recipient.
+        let wrapper = MpidMessageWrapper::PutHeader(header.clone());
+        let actions = unwrap_result!(recipient_manager.handle(&sender, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::InboxAdded(name.clone()),
+                        Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: MpidMessageWrapper::PutHeader(header.clone()),
+                        }]);
+        let wrapper = MpidMessageWrapper::Online;
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: MpidMessageWrapper::PutHeader(header.clone()),
+                        }]);
+
+        // Recipient's Client can query its inbox.
+        let wrapper = MpidMessageWrapper::InboxHas(vec![name.clone(), rand::random()]);
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        let response = MpidMessageWrapper::InboxHasResponse(vec![header.clone()]);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: response,
+                        }]);
+        let wrapper = MpidMessageWrapper::GetInboxHeaders;
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        let response = MpidMessageWrapper::GetInboxHeadersResponse(vec![header.clone()]);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: response,
+                        }]);
+        match recipient_manager.handle(&sender, MpidMessageWrapper::GetInboxHeaders, now) {
+            Err(Error::InvalidRequest) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Recipient retrieves the message via the sender's managers.
+        let wrapper = MpidMessageWrapper::GetMessage(header.clone());
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: MpidMessageWrapper::GetMessage(header.clone()),
+                        }]);
+        let wrapper = MpidMessageWrapper::GetMessage(header.clone());
+        let actions = unwrap_result!(sender_manager.handle(&recipient, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: MpidMessageWrapper::PutMessage(message.clone()),
+                        }]);
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let actions = unwrap_result!(recipient_manager.handle(&sender, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: MpidMessageWrapper::PutMessage(message.clone()),
+                        }]);
+
+        // Sender's Client can query its outbox.
+        let wrapper = MpidMessageWrapper::GetOutboxHeaders;
+        let actions = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+        let response = MpidMessageWrapper::GetOutboxHeadersResponse(vec![header.clone()]);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+
+        // Recipient deletes the message, which removes it from both inbox and outbox.
+        let wrapper = MpidMessageWrapper::DeleteMessage(name.clone());
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        assert_eq!(actions,
+                   vec![Action::InboxRemoved(name.clone()),
+                        Action::Send {
+                            dst: sender.clone(),
+                            wrapper: MpidMessageWrapper::DeleteHeader(name.clone()),
+                        }]);
+        let wrapper = MpidMessageWrapper::DeleteHeader(name.clone());
+        let actions = unwrap_result!(sender_manager.handle(&recipient, wrapper, now));
+        assert_eq!(actions, vec![Action::OutboxRemoved(name.clone())]);
+        assert!(!sender_manager.outbox().contains(&name));
+        assert!(!recipient_manager.inbox().contains(&name));
+        let wrapper = MpidMessageWrapper::GetMessage(header);
+        let actions = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        let response = MpidMessageWrapper::GetMessageFailure(name, MessagingError::NoSuchMessage);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: response,
+                        }]);
+    }
+
+    #[test]
+    fn failures() {
+        let now = messaging::seconds_since_epoch();
+        let (sender_public_key, sender_secret_key) = sign::gen_keypair();
+        let (recipient_public_key, _) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender.clone(), sender_public_key);
+        let mut sender_manager = MpidManager::new(sender.clone(), sender_public_key, &resolver);
+        let mut recipient_manager = MpidManager::new(recipient.clone(),
+                                                     recipient_public_key,
+                                                     &resolver);
+
+        // A message signed by the wrong key is rejected.
+        let (_, other_secret_key) = sign::gen_keypair();
+        let forged = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                     vec![],
+                                                     recipient.clone(),
+                                                     vec![],
+                                                     &other_secret_key));
+        let forged_name = unwrap_result!(forged.name());
+        let wrapper = MpidMessageWrapper::PutMessage(forged);
+        let actions = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+        let response = MpidMessageWrapper::PutMessageFailure(forged_name,
+                                                             MessagingError::InvalidSignature);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+        assert!(sender_manager.outbox().is_empty());
+
+        // Messages and headers created too long ago are rejected.
+        let created = now - 2 * 24 * 60 * 60;
+        let stale = unwrap_result!(MpidMessage::with_guid(sender.clone(),
+                                                          vec![],
+                                                          recipient.clone(),
+                                                          vec![],
+                                                          [1; GUID_SIZE],
+                                                          created,
+                                                          &sender_secret_key));
+        let stale_name = unwrap_result!(stale.name());
+        let response = MpidMessageWrapper::PutMessageFailure(stale_name,
+                                                             MessagingError::InvalidRequest);
+        let wrapper = MpidMessageWrapper::PutMessage(stale.clone());
+        assert_eq!(unwrap_result!(sender_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response.clone(),
+                        }]);
+        let wrapper = MpidMessageWrapper::PutHeader(stale.header().clone());
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+        assert!(sender_manager.outbox().is_empty());
+        assert!(recipient_manager.inbox().is_empty());
+
+        // Headers and messages passed on by another account's managers must verify against the
+        // sender's keys.
+        let forged = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                     vec![],
+                                                     recipient.clone(),
+                                                     vec![],
+                                                     &other_secret_key));
+        let forged_name = unwrap_result!(forged.name());
+        let wrapper = MpidMessageWrapper::PutHeader(forged.header().clone());
+        let response = MpidMessageWrapper::PutMessageFailure(forged_name,
+                                                             MessagingError::InvalidSignature);
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+        assert!(recipient_manager.inbox().is_empty());
+        match recipient_manager.handle(&sender, MpidMessageWrapper::PutMessage(forged), now) {
+            Err(Error::InvalidSignature) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // A header from a blocked sender is rejected, and the sender's managers then drop the
+        // message and inform the sender.
+        let message = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                      vec![],
+                                                      recipient.clone(),
+                                                      vec![],
+                                                      &sender_secret_key));
+        let name = unwrap_result!(message.name());
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let _ = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+        assert!(recipient_manager.block_sender(sender.clone()));
+        let wrapper = MpidMessageWrapper::PutHeader(message.header().clone());
+        let actions = unwrap_result!(recipient_manager.handle(&sender, wrapper, now));
+        let response = MpidMessageWrapper::PutMessageFailure(name.clone(),
+                                                             MessagingError::SenderBlocked);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response.clone(),
+                        }]);
+        assert!(recipient_manager.inbox().is_empty());
+        let actions = unwrap_result!(sender_manager.handle(&recipient, response.clone(), now));
+        assert_eq!(actions,
+                   vec![Action::OutboxRemoved(name.clone()),
+                        Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response.clone(),
+                        }]);
+        assert!(sender_manager.outbox().is_empty());
+
+        // Failure responses aren't themselves answered with failure responses.
+        match sender_manager.handle(&recipient, response, now) {
+            Err(Error::NoSuchMessage) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let wrapper = MpidMessageWrapper::DeleteMessage(name.clone());
+        let actions = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+        let response = MpidMessageWrapper::DeleteMessageFailure(name,
+                                                                MessagingError::NoSuchMessage);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+    }
+
+    #[test]
+    fn expiry() {
+        let (sender_public_key, sender_secret_key) = sign::gen_keypair();
+        let (recipient_public_key, _) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender.clone(), sender_public_key);
+        let mut sender_manager = MpidManager::new(sender.clone(), sender_public_key, &resolver);
+        let mut recipient_manager = MpidManager::new(recipient.clone(),
+                                                     recipient_public_key,
+                                                     &resolver);
+        let now = messaging::seconds_since_epoch();
+
+        // Messages which have already expired are rejected.
+        let expired = expiring_message(&sender, &recipient, Some(now), &sender_secret_key);
+        let expired_name = unwrap_result!(expired.name());
+        let response = MpidMessageWrapper::PutMessageFailure(expired_name,
+                                                             MessagingError::InvalidRequest);
+        let wrapper = MpidMessageWrapper::PutMessage(expired.clone());
+        assert_eq!(unwrap_result!(sender_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response.clone(),
+                        }]);
+        let wrapper = MpidMessageWrapper::PutHeader(expired.header().clone());
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+
+        // Put a message expiring soon, one expiring later and one which doesn't expire.
+        let soon = expiring_message(&sender, &recipient, Some(now + 50), &sender_secret_key);
+        let later = expiring_message(&sender, &recipient, Some(now + 100), &sender_secret_key);
+        let forever = expiring_message(&sender, &recipient, None, &sender_secret_key);
+        for message in &[&soon, &later, &forever] {
+            let wrapper = MpidMessageWrapper::PutMessage((*message).clone());
+            let _ = unwrap_result!(sender_manager.handle(&sender, wrapper, now));
+            let wrapper = MpidMessageWrapper::PutHeader(message.header().clone());
+            let _ = unwrap_result!(recipient_manager.handle(&sender, wrapper, now));
+        }
+        assert_eq!(sender_manager.outbox().len(), 3);
+        assert_eq!(recipient_manager.inbox().len(), 3);
+        let soon_name = unwrap_result!(soon.name());
+        let later_name = unwrap_result!(later.name());
+
+        // The recipient's managers purge the expired header and notify the sender's managers,
+        // which purge the message.
+        let now = now + 50;
+        let notification = MpidMessageWrapper::MessageExpired(soon_name.clone());
+        assert_eq!(unwrap_result!(recipient_manager.remove_expired(now)),
+                   vec![Action::InboxRemoved(soon_name.clone()),
+                        Action::Send {
+                            dst: sender.clone(),
+                            wrapper: notification.clone(),
+                        }]);
+        assert_eq!(recipient_manager.inbox().len(), 2);
+        assert!(unwrap_result!(recipient_manager.remove_expired(now)).is_empty());
+        assert_eq!(unwrap_result!(sender_manager.handle(&recipient, notification.clone(), now)),
+                   vec![Action::OutboxRemoved(soon_name)]);
+        assert_eq!(sender_manager.outbox().len(), 2);
+        assert!(unwrap_result!(sender_manager.handle(&recipient, notification, now)).is_empty());
+
+        // Notifications for messages which haven't expired by the local clock are ignored.
+        let notification = MpidMessageWrapper::MessageExpired(later_name.clone());
+        assert!(unwrap_result!(sender_manager.handle(&recipient, notification.clone(), now))
+                    .is_empty());
+        assert_eq!(sender_manager.outbox().len(), 2);
+
+        // Once it expires, the sender's managers purge the message and notify the recipient's
+        // managers.
+        let now = now + 50;
+        assert_eq!(unwrap_result!(sender_manager.remove_expired(now)),
+                   vec![Action::OutboxRemoved(later_name.clone()),
+                        Action::Send {
+                            dst: recipient.clone(),
+                            wrapper: notification.clone(),
+                        }]);
+        assert_eq!(sender_manager.outbox().len(), 1);
+        assert!(unwrap_result!(sender_manager.remove_expired(u64::max_value())).is_empty());
+
+        // Notifications of expired messages from anyone but the counterpart are ignored.
+        let wrapper = notification.clone();
+        assert!(unwrap_result!(recipient_manager.handle(&rand::random(), wrapper, now)).is_empty());
+        assert_eq!(recipient_manager.inbox().len(), 2);
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, notification, now)),
+                   vec![Action::InboxRemoved(later_name)]);
+        assert_eq!(recipient_manager.inbox().len(), 1);
+    }
+
+    #[test]
+    fn replays() {
+        let now = messaging::seconds_since_epoch();
+        let (sender_public_key, sender_secret_key) = sign::gen_keypair();
+        let (recipient_public_key, _) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender.clone(), sender_public_key);
+        let mut sender_manager = MpidManager::new(sender.clone(), sender_public_key, &resolver);
+        let mut recipient_manager = MpidManager::new(recipient.clone(),
+                                                     recipient_public_key,
+                                                     &resolver);
+        let guid = [7; GUID_SIZE];
+        let message = unwrap_result!(MpidMessage::with_guid(sender.clone(),
+                                                            vec![],
+                                                            recipient.clone(),
+                                                            vec![1, 2, 3],
+                                                            guid,
+                                                            now,
+                                                            &sender_secret_key));
+        let name = unwrap_result!(message.name());
+        let header = message.header().clone();
+        let put_message = MpidMessageWrapper::PutMessage(message.clone());
+        let put_header = MpidMessageWrapper::PutHeader(header.clone());
+        assert_eq!(unwrap_result!(sender_manager.handle(&sender, put_message.clone(), now)).len(),
+                   2);
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, put_header.clone(), now)).len(),
+                   2);
+
+        // Once the message has been deleted, replays are still ignored.
+        let wrapper = MpidMessageWrapper::DeleteMessage(name.clone());
+        let _ = unwrap_result!(recipient_manager.handle(&recipient, wrapper, now));
+        let wrapper = MpidMessageWrapper::DeleteHeader(name.clone());
+        let _ = unwrap_result!(sender_manager.handle(&recipient, wrapper, now));
+        assert!(unwrap_result!(sender_manager.handle(&sender, put_message, now)).is_empty());
+        assert!(unwrap_result!(recipient_manager.handle(&sender, put_header.clone(), now))
+                    .is_empty());
+        assert!(sender_manager.outbox().is_empty());
+        assert!(recipient_manager.inbox().is_empty());
+
+        // A different message reusing the GUID is rejected.
+        let reused = unwrap_result!(MpidMessage::with_guid(sender.clone(),
+                                                           vec![],
+                                                           recipient.clone(),
+                                                           vec![4, 5, 6],
+                                                           guid,
+                                                           now,
+                                                           &sender_secret_key));
+        let reused_name = unwrap_result!(reused.name());
+        let wrapper = MpidMessageWrapper::PutMessage(reused.clone());
+        let response = MpidMessageWrapper::PutMessageFailure(reused_name.clone(),
+                                                             MessagingError::InvalidRequest);
+        assert_eq!(unwrap_result!(sender_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response.clone(),
+                        }]);
+        let wrapper = MpidMessageWrapper::PutHeader(reused.header().clone());
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, wrapper, now)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+        assert!(recipient_manager.inbox().is_empty());
+
+        // Replays are remembered for as long as the manager's clock skew policy accepts them.
+        let policy = ClockSkewPolicy {
+            max_future_skew: 10,
+            max_age: Some(100),
+        };
+        recipient_manager.set_clock_skew_policy(policy);
+        assert_eq!(*recipient_manager.clock_skew_policy(), policy);
+        assert!(unwrap_result!(recipient_manager.handle(&sender, put_header.clone(), now + 100))
+                    .is_empty());
+        let response = MpidMessageWrapper::PutMessageFailure(name,
+                                                             MessagingError::InvalidRequest);
+        assert_eq!(unwrap_result!(recipient_manager.handle(&sender, put_header, now + 110)),
+                   vec![Action::Send {
+                            dst: sender.clone(),
+                            wrapper: response,
+                        }]);
+        assert!(recipient_manager.inbox().is_empty());
+    }
+
+    #[test]
+    fn paging() {
+        let now = messaging::seconds_since_epoch();
+        let (public_key, secret_key) = sign::gen_keypair();
+        let owner: XorName = rand::random();
+        let mut manager = MpidManager::new(owner.clone(), public_key, MemoryKeyResolver::new());
+        let mut names = vec![];
+        for _ in 0..MAX_HEADER_PAGE_SIZE + 1 {
+            let message = unwrap_result!(MpidMessage::new(owner.clone(),
+                                                          vec![],
+                                                          rand::random(),
+                                                          vec![],
+                                                          &secret_key));
+            names.push(unwrap_result!(message.name()));
+            let wrapper = MpidMessageWrapper::PutMessage(message);
+            assert_eq!(unwrap_result!(manager.handle(&owner, wrapper, now)).len(), 2);
+        }
+        names.sort();
+
+        // The unpaged request only returns the first page, so the response can be deserialised.
+        let actions = unwrap_result!(manager.handle(&owner,
+                                                    MpidMessageWrapper::GetOutboxHeaders,
+                                                    now));
+        assert_eq!(actions.len(), 1);
+        let headers = match actions[0] {
+            Action::Send { wrapper: MpidMessageWrapper::GetOutboxHeadersResponse(ref headers),
+                           .. } => headers.clone(),
+            ref action => panic!("Unexpected action: {:?}", action),
+        };
+        let header_names = headers.iter()
+                                  .map(|header| unwrap_result!(header.name()))
+                                  .collect::<Vec<_>>();
+        assert_eq!(header_names, &names[..MAX_HEADER_PAGE_SIZE]);
+        let response = MpidMessageWrapper::GetOutboxHeadersResponse(headers);
+        let serialised = unwrap_result!(serialise(&response));
+        assert_eq!(unwrap_result!(MpidMessageWrapper::deserialise(&serialised)), response);
+
+        // The rest is retrieved via the paged request.
+        let cursor = names[MAX_HEADER_PAGE_SIZE - 1].clone();
+        let wrapper = MpidMessageWrapper::GetOutboxHeadersPage(Some(cursor),
+                                                               MAX_HEADER_PAGE_SIZE as u32);
+        let actions = unwrap_result!(manager.handle(&owner, wrapper, now));
+        assert_eq!(actions.len(), 1);
+        match actions[0] {
+            Action::Send { wrapper: MpidMessageWrapper::GetOutboxHeadersPageResponse(ref page),
+                           .. } => {
+                assert_eq!(page.headers.len(), 1);
+                assert_eq!(unwrap_result!(page.headers[0].name()), names[MAX_HEADER_PAGE_SIZE]);
+                assert!(page.next_cursor.is_none());
+            }
+            ref action => panic!("Unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn persistence() {
+        let now = messaging::seconds_since_epoch();
+        let dir = env::temp_dir().join(format!("safe_network_common_{}",
+                                               rand::random::<XorName>().as_hex()));
+        let (owner_public_key, owner_secret_key) = sign::gen_keypair();
+        let (sender_public_key, sender_secret_key) = sign::gen_keypair();
+        let owner: XorName = rand::random();
+        let sender: XorName = rand::random();
+        let mut resolver = MemoryKeyResolver::new();
+        resolver.insert(sender.clone(), sender_public_key);
+        let sent = unwrap_result!(MpidMessage::new(owner.clone(),
+                                                   vec![],
+                                                   rand::random(),
+                                                   vec![1, 2, 3],
+                                                   &owner_secret_key));
+        let sent_name = unwrap_result!(sent.name());
+        let received = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                       vec![],
+                                                       owner.clone(),
+                                                       vec![4, 5, 6],
+                                                       &sender_secret_key));
+        let received_name = unwrap_result!(received.name());
+
+        // The inbox and outbox survive the manager being reconstructed over the same store.
+        {
+            let store = unwrap_result!(FileMailboxStore::open(&dir));
+            let mut manager = unwrap_result!(MpidManager::with_store(owner.clone(),
+                                                                     owner_public_key,
+                                                                     &resolver,
+                                                                     store));
+            let wrapper = MpidMessageWrapper::PutMessage(sent.clone());
+            assert_eq!(unwrap_result!(manager.handle(&owner, wrapper, now)).len(), 2);
+            let wrapper = MpidMessageWrapper::PutHeader(received.header().clone());
+            assert_eq!(unwrap_result!(manager.handle(&sender, wrapper, now)).len(), 2);
+        }
+        {
+            let store = unwrap_result!(FileMailboxStore::open(&dir));
+            let mut manager = unwrap_result!(MpidManager::with_store(owner.clone(),
+                                                                     owner_public_key,
+                                                                     &resolver,
+                                                                     store));
+            assert_eq!(manager.outbox().get(&sent_name), Some(&sent));
+            assert_eq!(manager.inbox().get(&received_name), Some(received.header()));
+            let wrapper = MpidMessageWrapper::DeleteMessage(sent_name.clone());
+            let _ = unwrap_result!(manager.handle(&owner, wrapper, now));
+            unwrap_result!(manager.store_mut().compact());
+        }
+        {
+            let store = unwrap_result!(FileMailboxStore::open(&dir));
+            let manager = unwrap_result!(MpidManager::with_store(owner.clone(),
+                                                                 owner_public_key,
+                                                                 &resolver,
+                                                                 store));
+            assert!(manager.outbox().is_empty());
+            assert_eq!(manager.inbox().len(), 1);
+            assert_eq!(unwrap_result!(manager.store().list(&owner)), vec![received_name]);
+        }
+
+        unwrap_result!(fs::remove_dir_all(&dir));
+    }
+}
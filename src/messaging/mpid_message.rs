@@ -18,13 +18,28 @@
 /// Maximum allowed length for a [message's `body`](struct.MpidMessage.html#method.new) (101,760
 /// bytes).
 pub const MAX_BODY_SIZE: usize = 102400 - 512 - super::MAX_HEADER_METADATA_SIZE;
+/// Maximum allowed length of a serialised message (102,528 bytes), as accepted by
+/// [`MpidMessage::deserialise()`](struct.MpidMessage.html#method.deserialise).
+pub const MAX_SERIALISED_MESSAGE_SIZE: usize = super::MAX_SERIALISED_HEADER_SIZE + MAX_BODY_SIZE +
+                                               256;
+/// Maximum allowed length of the plaintext body of an
+/// [encrypted message](struct.MpidMessage.html#method.new_encrypted) (101,712 bytes), leaving room
+/// for the encryption overhead within [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
+pub const MAX_ENCRYPTED_BODY_SIZE: usize = MAX_BODY_SIZE - super::SEALED_BOX_OVERHEAD;
 
 use std::fmt::{self, Debug, Formatter};
 
 use messaging;
 use maidsafe_utilities::serialisation::serialise;
-use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
-use super::{Error, MpidHeader};
+use rand::{self, Rng};
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::sealedbox;
+use sodiumoxide::crypto::sign::Signature;
+use super::{Codec, Error, GUID_SIZE, KeyResolver, MAX_DECOMPRESSED_BODY_SIZE,
+            MAX_ENCRYPTED_METADATA_SIZE, MpidHeader, MpidPublicId};
+use super::mpid_header;
+use super::padding::SizeClasses;
+use super::signing::{self, Domain, Signer, Verifier};
 use xor_name::XorName;
 
 #[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
@@ -53,32 +68,96 @@ impl MpidMessage {
     /// `body` is arbitrary, user-supplied data representing the main portion of the message.  It
     /// must not exceed [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).  It can be empty if desired.
     ///
+    /// `signer` signs both the header and the message; see [`Signer`](trait.Signer.html).
+    ///
     /// An error will be returned if `body` exceeds `MAX_BODY_SIZE`, if
-    /// [MpidHeader::new()](struct.MpidHeader.html#method.new) fails or if
-    /// serialisation during the signing process fails.
-    pub fn new(sender: XorName,
-               metadata: Vec<u8>,
-               recipient: XorName,
-               body: Vec<u8>,
-               secret_key: &SecretKey)
-               -> Result<MpidMessage, Error> {
-        if body.len() > MAX_BODY_SIZE {
-            return Err(Error::BodyTooLarge);
-        }
+    /// [MpidHeader::new()](struct.MpidHeader.html#method.new) fails, if serialisation during the
+    /// signing process fails or if `signer` fails.
+    pub fn new<S: Signer + ?Sized>(sender: XorName,
+                                   metadata: Vec<u8>,
+                                   recipient: XorName,
+                                   body: Vec<u8>,
+                                   signer: &S)
+                                   -> Result<MpidMessage, Error> {
+        Self::new_with_rng(sender,
+                           metadata,
+                           recipient,
+                           body,
+                           &mut rand::thread_rng(),
+                           messaging::seconds_since_epoch(),
+                           signer)
+    }
 
-        let header = try!(MpidHeader::new(sender, metadata, secret_key));
+    /// As per [`new()`](#method.new), except that the header's GUID is drawn from `rng` and the
+    /// caller supplies its creation timestamp.  See
+    /// [MpidHeader::new_with_rng()](struct.MpidHeader.html#method.new_with_rng).
+    pub fn new_with_rng<R: Rng, S: Signer + ?Sized>(sender: XorName,
+                                                    metadata: Vec<u8>,
+                                                    recipient: XorName,
+                                                    body: Vec<u8>,
+                                                    rng: &mut R,
+                                                    created: u64,
+                                                    signer: &S)
+                                                    -> Result<MpidMessage, Error> {
+        Self::with_guid(sender,
+                        metadata,
+                        recipient,
+                        body,
+                        mpid_header::random_guid(rng),
+                        created,
+                        signer)
+    }
 
-        let detail = Detail {
-            recipient: recipient,
-            body: body,
-        };
+    /// As per [`new()`](#method.new), except that the caller supplies the header's GUID and
+    /// creation timestamp.  See [MpidHeader::with_guid()](struct.MpidHeader.html#method.with_guid).
+    pub fn with_guid<S: Signer + ?Sized>(sender: XorName,
+                                         metadata: Vec<u8>,
+                                         recipient: XorName,
+                                         body: Vec<u8>,
+                                         guid: [u8; GUID_SIZE],
+                                         created: u64,
+                                         signer: &S)
+                                         -> Result<MpidMessage, Error> {
+        new_message(mpid_header::Detail::new(sender, metadata, guid, created),
+                    recipient,
+                    body,
+                    signer)
+    }
 
-        let recipient_and_body = try!(serialise(&detail));
-        Ok(MpidMessage {
-            header: header,
-            detail: detail,
-            signature: sign::sign_detached(&recipient_and_body, secret_key),
-        })
+    /// As per [`new()`](#method.new), except that `metadata` and `body` are encrypted to
+    /// `recipient`'s encryption key, so that only the recipient can read them.  Only the
+    /// ciphertexts are signed, stored and seen by the MpidManagers; the recipient recovers the
+    /// plaintexts via [`MpidHeader::open_metadata()`](struct.MpidHeader.html#method.open_metadata)
+    /// and [`open()`](#method.open).
+    ///
+    /// The message is addressed to `recipient.name()`.  `metadata` must not exceed
+    /// [`MAX_ENCRYPTED_METADATA_SIZE`](constant.MAX_ENCRYPTED_METADATA_SIZE.html) and `body` must
+    /// not exceed [`MAX_ENCRYPTED_BODY_SIZE`](constant.MAX_ENCRYPTED_BODY_SIZE.html).  The
+    /// encryption is anonymous; the sender is authenticated by the message's signature as usual.
+    pub fn new_encrypted<S: Signer + ?Sized>(sender: XorName,
+                                             metadata: Vec<u8>,
+                                             recipient: &MpidPublicId,
+                                             body: &[u8],
+                                             signer: &S)
+                                             -> Result<MpidMessage, Error> {
+        if metadata.len() > MAX_ENCRYPTED_METADATA_SIZE {
+            return Err(Error::MetadataTooLarge);
+        }
+        if body.len() > MAX_ENCRYPTED_BODY_SIZE {
+            return Err(Error::BodyTooLarge);
+        }
+        let guid = mpid_header::random_guid(&mut rand::thread_rng());
+        let metadata = mpid_header::seal_metadata(&metadata, recipient.encryption_key());
+        let mut header_detail = mpid_header::Detail::new(sender,
+                                                         metadata,
+                                                         guid,
+                                                         messaging::seconds_since_epoch());
+        header_detail.encrypted_metadata = true;
+        header_detail.encrypted_body = true;
+        new_message(header_detail,
+                    recipient.name(),
+                    seal_body(body, recipient.encryption_key()),
+                    signer)
     }
 
     /// Getter for `MpidHeader` member, created when calling `new()`.
@@ -91,11 +170,50 @@ impl MpidMessage {
         &self.detail.recipient
     }
 
-    /// Arbitrary, user-supplied data representing the main portion of the message.
-    pub fn body(&self) -> &Vec<u8> {
+    /// Arbitrary, user-supplied data representing the main portion of the message.  Any
+    /// [padding](struct.MpidHeader.html#method.is_padded) is removed and any
+    /// [compression](struct.MpidHeader.html#method.codec) undone, but this is the ciphertext if the
+    /// body is encrypted; use [`open()`](#method.open) for that.
+    ///
+    /// An error will be returned if the padding or compression is invalid.
+    pub fn body(&self) -> Result<Vec<u8>, Error> {
+        if self.is_encrypted() {
+            return Ok(self.detail.body.clone());
+        }
+        decode_body(&self.header, self.detail.body.clone())
+    }
+
+    /// The body as signed and transmitted, i.e. after any compression, padding and encryption.
+    /// Only this form is subject to [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
+    pub fn raw_body(&self) -> &Vec<u8> {
         &self.detail.body
     }
 
+    /// Returns whether the body is encrypted to the recipient.
+    pub fn is_encrypted(&self) -> bool {
+        self.header.is_body_encrypted()
+    }
+
+    /// Decrypts the body of an [encrypted message](#method.new_encrypted) using the recipient's
+    /// encryption key pair.
+    ///
+    /// Any padding and compression is removed from the decrypted body.
+    ///
+    /// An error will be returned if the message isn't encrypted, if the body can't be decrypted
+    /// with the given keys or if the padding or compression is invalid.  The signatures aren't
+    /// checked; use [`verify()`](#method.verify) for that.
+    pub fn open(&self,
+                public_key: &box_::PublicKey,
+                secret_key: &box_::SecretKey)
+                -> Result<Vec<u8>, Error> {
+        if !self.is_encrypted() {
+            return Err(Error::DecryptionFailed);
+        }
+        let body = try!(sealedbox::open(&self.detail.body, public_key, secret_key)
+                            .map_err(|()| Error::DecryptionFailed));
+        decode_body(&self.header, body)
+    }
+
     /// The name of the message, equivalent to the
     /// [`MpidHeader::name()`](../struct.MpidHeader.html#method.name).  As per that getter, this is
     /// relatively expensive, so its use should be minimised.
@@ -103,16 +221,67 @@ impl MpidMessage {
         self.header.name()
     }
 
-    /// Validates the message and header signatures against the provided `PublicKey`.
-    pub fn verify(&self, public_key: &PublicKey) -> bool {
+    /// Deserialises a message received from an untrusted source.
+    ///
+    /// Unlike deserialising via `RustcDecodable`, input longer than
+    /// [`MAX_SERIALISED_MESSAGE_SIZE`](constant.MAX_SERIALISED_MESSAGE_SIZE.html) is rejected
+    /// before decoding, and the decoded message is checked via [`validate()`](#method.validate).
+    /// The signatures aren't checked; use [`verify()`](#method.verify) for that.
+    pub fn deserialise(serialised: &[u8]) -> Result<MpidMessage, Error> {
+        let message: MpidMessage =
+            try!(messaging::deserialise_bounded(serialised, MAX_SERIALISED_MESSAGE_SIZE));
+        try!(message.validate());
+        Ok(message)
+    }
+
+    /// Checks the invariants enforced by [`new()`](#method.new), i.e. that `body` doesn't exceed
+    /// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html), that the header is valid and that the header
+    /// is bound to the message's recipient and body.  The body of an encrypted message must also be
+    /// long enough to be a ciphertext.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.detail.body.len() > MAX_BODY_SIZE {
+            return Err(Error::BodyTooLarge);
+        }
+        try!(self.header.validate());
+        if !self.header.commits_to(&self.detail.recipient, &self.detail.body) ||
+           (self.is_encrypted() && self.detail.body.len() < super::SEALED_BOX_OVERHEAD) {
+            return Err(Error::Malformed);
+        }
+        Ok(())
+    }
+
+    /// Validates the message and header signatures via `verifier`, e.g. the sender's `PublicKey`,
+    /// and that the header is bound to this message's recipient and body.
+    ///
+    /// A recipient holding the header it was notified about can check that a fetched message
+    /// matches it by comparing that header with [`header()`](#method.header) and calling this.
+    pub fn verify<V: Verifier + ?Sized>(&self, verifier: &V) -> bool {
+        if !self.header.commits_to(&self.detail.recipient, &self.detail.body) {
+            return false;
+        }
         match serialise(&self.detail) {
             Ok(recipient_and_body) => {
-                sign::verify_detached(&self.signature, &recipient_and_body, public_key) &&
-                self.header.verify(public_key)
+                signing::verify_detached(Domain::Message,
+                                         &self.signature,
+                                         &recipient_and_body,
+                                         verifier) && self.header.verify(verifier)
             }
             Err(_) => false,
         }
     }
+
+    /// Validates the message as per [`verify()`](#method.verify), using the signing key of
+    /// `sender`, and that `sender` is the header's sender.  See
+    /// [MpidHeader::verify_from_sender()](struct.MpidHeader.html#method.verify_from_sender).
+    pub fn verify_from_sender(&self, sender: &MpidPublicId) -> bool {
+        *self.header.sender() == sender.name() && self.verify(sender)
+    }
+
+    /// Validates the message as per [`verify()`](#method.verify), using the keys of its sender as
+    /// looked up via `resolver`.  Returns `true` if any of the keys verifies the message.
+    pub fn verify_with_resolver<K: KeyResolver + ?Sized>(&self, resolver: &K) -> bool {
+        resolver.resolve(self.header.sender()).iter().any(|public_key| self.verify(public_key))
+    }
 }
 
 impl Debug for MpidMessage {
@@ -126,13 +295,62 @@ impl Debug for MpidMessage {
     }
 }
 
+// Constructs a signed message whose header is built from `header_detail`, binding the header to
+// `recipient` and `body`.  `body` must already be compressed and padded as per `header_detail`.
+pub fn new_message<S: Signer + ?Sized>(mut header_detail: mpid_header::Detail,
+                                       recipient: XorName,
+                                       body: Vec<u8>,
+                                       signer: &S)
+                                       -> Result<MpidMessage, Error> {
+    if body.len() > MAX_BODY_SIZE {
+        return Err(Error::BodyTooLarge);
+    }
+
+    header_detail.message_hash = Some(mpid_header::hash_message(&recipient, &body));
+    let header = try!(mpid_header::new_header(header_detail, signer));
+
+    let detail = Detail {
+        recipient: recipient,
+        body: body,
+    };
+
+    let recipient_and_body = try!(serialise(&detail));
+    let signature = try!(signing::sign_detached(Domain::Message, &recipient_and_body, signer));
+    Ok(MpidMessage {
+        header: header,
+        detail: detail,
+        signature: signature,
+    })
+}
+
+// Removes any padding, then any compression, from the plaintext `body` of a message with `header`.
+fn decode_body(header: &MpidHeader, body: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let body = if header.is_padded() {
+        try!(SizeClasses::body(header.is_body_encrypted()).unpad(&body))
+    } else {
+        body
+    };
+    match header.codec() {
+        Codec::None => Ok(body),
+        codec => codec.decompress(&body, MAX_DECOMPRESSED_BODY_SIZE),
+    }
+}
+
+// Encrypts `body` such that it can only be decrypted with the secret key matching `public_key`.
+pub fn seal_body(body: &[u8], public_key: &box_::PublicKey) -> Vec<u8> {
+    sealedbox::seal(body, public_key)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use rand;
     use sodiumoxide::crypto::sign;
     use xor_name::XorName;
-    use messaging;
+    use maidsafe_utilities::serialisation::serialise;
+    use messaging::{self, Codec, Error, MAX_DECOMPRESSED_BODY_SIZE, MpidHeader, MpidPublicId};
+    use messaging::mpid_header;
+    use sodiumoxide::crypto::box_;
 
     #[test]
     fn full() {
@@ -148,7 +366,7 @@ mod test {
                                                           recipient.clone(),
                                                           vec![],
                                                           &secret_key));
-            assert!(message.body().is_empty());
+            assert!(unwrap_result!(message.body()).is_empty());
         }
         let mut body = messaging::generate_random_bytes(MAX_BODY_SIZE);
         let message = unwrap_result!(MpidMessage::new(sender.clone(),
@@ -156,7 +374,7 @@ mod test {
                                                       recipient.clone(),
                                                       body.clone(),
                                                       &secret_key));
-        assert!(*message.body() == body);
+        assert!(unwrap_result!(message.body()) == body);
         body.push(0);
         assert!(MpidMessage::new(sender.clone(),
                                  metadata.clone(),
@@ -174,5 +392,166 @@ mod test {
             public_key.0[0] = 0;
         }
         assert!(!message.verify(&public_key));
+
+        // Check that messages with the same GUID and timestamp are identical.
+        let message2 = unwrap_result!(MpidMessage::with_guid(sender.clone(),
+                                                             metadata.clone(),
+                                                             recipient.clone(),
+                                                             body.clone(),
+                                                             *message.header().guid(),
+                                                             message.header().created(),
+                                                             &secret_key));
+        let message3 = unwrap_result!(MpidMessage::with_guid(sender.clone(),
+                                                             metadata.clone(),
+                                                             recipient.clone(),
+                                                             body.clone(),
+                                                             *message.header().guid(),
+                                                             message.header().created(),
+                                                             &secret_key));
+        assert_eq!(message2, message3);
+        assert_eq!(message2, message);
+    }
+
+    #[test]
+    fn header_binding() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let message1 = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       vec![1],
+                                                       &secret_key));
+        let message2 = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       vec![2],
+                                                       &secret_key));
+        assert!(message1.header().commits_to(&recipient, &[1]));
+        assert!(!message1.header().commits_to(&recipient, &[2]));
+        assert!(!message1.header().commits_to(&sender, &[1]));
+
+        // Pairing one message's body with another's header fails verification, even though both
+        // are validly signed.
+        let mut spliced = message1.clone();
+        spliced.header = message2.header().clone();
+        assert!(!spliced.verify(&public_key));
+        assert!(message1.verify(&public_key));
+
+        // A standalone header isn't bound to any message.
+        let header = unwrap_result!(MpidHeader::new(sender, vec![], &secret_key));
+        assert!(header.message_hash().is_none());
+        assert!(!header.commits_to(&recipient, &[1]));
+    }
+
+    #[test]
+    fn encrypted() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (encryption_key, decryption_key) = box_::gen_keypair();
+        let (recipient_signing_key, _) = sign::gen_keypair();
+        let recipient = MpidPublicId::new(recipient_signing_key, encryption_key);
+        let sender: XorName = rand::random();
+
+        // The body is only readable with the recipient's keys, and the ciphertext is signed.
+        let metadata = b"Subject".to_vec();
+        let body = messaging::generate_random_bytes(MAX_ENCRYPTED_BODY_SIZE);
+        let message = unwrap_result!(MpidMessage::new_encrypted(sender,
+                                                                metadata.clone(),
+                                                                &recipient,
+                                                                &body,
+                                                                &secret_key));
+        assert!(message.is_encrypted());
+        assert!(message.header().is_body_encrypted());
+        assert_eq!(*message.recipient(), recipient.name());
+        assert!(message.raw_body().len() <= MAX_BODY_SIZE);
+        assert!(*message.raw_body() != body);
+        assert!(message.verify(&public_key));
+        unwrap_result!(message.validate());
+        assert_eq!(unwrap_result!(message.open(&encryption_key, &decryption_key)), body);
+        let (other_encryption_key, other_decryption_key) = box_::gen_keypair();
+        assert!(message.open(&other_encryption_key, &other_decryption_key).is_err());
+        assert!(message.header().is_metadata_encrypted());
+        assert_eq!(message.header().raw_metadata().len(), messaging::MAX_HEADER_METADATA_SIZE);
+        assert_eq!(unwrap_result!(message.header().open_metadata(&encryption_key,
+                                                                 &decryption_key)),
+                   metadata);
+
+        // The plaintext size limit leaves room for the encryption overhead.
+        let mut too_large = body.clone();
+        too_large.push(0);
+        assert!(MpidMessage::new_encrypted(sender, vec![], &recipient, &too_large, &secret_key)
+                    .is_err());
+
+        // Unencrypted messages can't be opened.
+        let plain = unwrap_result!(MpidMessage::new(sender,
+                                                    vec![],
+                                                    recipient.name(),
+                                                    body,
+                                                    &secret_key));
+        assert!(!plain.is_encrypted());
+        assert!(plain.open(&encryption_key, &decryption_key).is_err());
+    }
+
+    #[test]
+    fn compressed() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let compressed_message = |data: &[u8]| {
+            let mut detail = mpid_header::Detail::new(sender.clone(), vec![], [0; GUID_SIZE], 0);
+            detail.codec = Codec::Deflate;
+            new_message(detail,
+                        recipient.clone(),
+                        unwrap_result!(Codec::Deflate.compress(data)),
+                        &secret_key)
+        };
+
+        // The compressed form is signed and serialised, and decompressed on reading.
+        let body = vec![1; MAX_DECOMPRESSED_BODY_SIZE];
+        let message = unwrap_result!(compressed_message(&body));
+        assert!(message.verify(&public_key));
+        assert!(message.raw_body().len() < body.len() / 100);
+        assert_eq!(unwrap_result!(message.body()), body);
+        let serialised = unwrap_result!(serialise(&message));
+        let deserialised = unwrap_result!(MpidMessage::deserialise(&serialised));
+        assert_eq!(deserialised, message);
+        assert_eq!(unwrap_result!(deserialised.body()), body);
+
+        // Bodies which decompress beyond the limit, or don't decompress, are rejected on reading,
+        // though the messages themselves are valid.
+        let message = unwrap_result!(compressed_message(&vec![1; MAX_DECOMPRESSED_BODY_SIZE + 1]));
+        match message.body() {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let mut detail = mpid_header::Detail::new(sender.clone(), vec![], [0; GUID_SIZE], 0);
+        detail.codec = Codec::Deflate;
+        let message = unwrap_result!(new_message(detail,
+                                                 recipient.clone(),
+                                                 vec![0xff; 10],
+                                                 &secret_key));
+        assert!(message.verify(&public_key));
+        match message.body() {
+            Err(Error::DecompressionFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Uncompressed bodies are returned as they are, and encrypted ones are left sealed.
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      recipient.clone(),
+                                                      vec![1, 2, 3],
+                                                      &secret_key));
+        assert_eq!(unwrap_result!(message.body()), vec![1, 2, 3]);
+        assert_eq!(*message.raw_body(), vec![1, 2, 3]);
+        let (encryption_key, decryption_key) = box_::gen_keypair();
+        let recipient_id = MpidPublicId::new(public_key, encryption_key);
+        let message = unwrap_result!(MpidMessage::new_encrypted(sender,
+                                                                vec![],
+                                                                &recipient_id,
+                                                                &[1, 2, 3],
+                                                                &secret_key));
+        assert_eq!(unwrap_result!(message.body()), *message.raw_body());
+        assert_eq!(unwrap_result!(message.open(&encryption_key, &decryption_key)), vec![1, 2, 3]);
     }
 }
@@ -0,0 +1,498 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use messaging;
+use rand;
+use sodiumoxide::crypto::box_;
+use super::{Codec, Error, GUID_SIZE, MAX_BODY_SIZE, MAX_DECOMPRESSED_BODY_SIZE,
+            MAX_ENCRYPTED_BODY_SIZE, MAX_ENCRYPTED_METADATA_SIZE, MAX_HEADER_METADATA_SIZE,
+            MpidMessage, MpidPublicId, PADDING_OVERHEAD, Priority};
+use super::{mpid_header, mpid_message};
+use super::padding::SizeClasses;
+use super::signing::Signer;
+use xor_name::XorName;
+
+/// A constraint violated by the fields of an
+/// [`MpidMessageBuilder`](struct.MpidMessageBuilder.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationError {
+    /// No sender was set.
+    MissingSender,
+    /// No recipient was set.
+    MissingRecipient,
+    /// The metadata exceeds [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html),
+    /// or [`MAX_ENCRYPTED_METADATA_SIZE`](constant.MAX_ENCRYPTED_METADATA_SIZE.html) if it's to be
+    /// encrypted.  Padding reduces the limit by
+    /// [`PADDING_OVERHEAD`](constant.PADDING_OVERHEAD.html) for unencrypted metadata.
+    MetadataTooLarge,
+    /// The body exceeds [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html),
+    /// [`MAX_ENCRYPTED_BODY_SIZE`](constant.MAX_ENCRYPTED_BODY_SIZE.html) if it's to be encrypted,
+    /// or [`MAX_DECOMPRESSED_BODY_SIZE`](constant.MAX_DECOMPRESSED_BODY_SIZE.html) if it's to be
+    /// compressed.  Padding reduces the first two limits by
+    /// [`PADDING_OVERHEAD`](constant.PADDING_OVERHEAD.html).
+    BodyTooLarge,
+    /// The expiry time isn't in the future.
+    ExpiryInPast,
+}
+
+/// Builder for [`MpidMessage`](struct.MpidMessage.html)s.
+///
+/// The sender and recipient must be set; all other fields are optional.  Metadata and body default
+/// to empty, the GUID to a random one, the creation timestamp to the current time and the priority
+/// to `Priority::Normal`, while the message doesn't expire, isn't compressed or padded and replies
+/// go to the sender unless set otherwise.
+#[derive(Clone, Debug, Default)]
+pub struct MpidMessageBuilder {
+    sender: Option<XorName>,
+    recipient: Option<XorName>,
+    metadata: Vec<u8>,
+    body: Vec<u8>,
+    guid: Option<[u8; GUID_SIZE]>,
+    expiry: Option<u64>,
+    priority: Priority,
+    reply_to: Option<XorName>,
+    encryption_key: Option<box_::PublicKey>,
+    created: Option<u64>,
+    codec: Codec,
+    padded: bool,
+}
+
+impl MpidMessageBuilder {
+    /// Constructs a builder with no fields set.
+    pub fn new() -> MpidMessageBuilder {
+        Default::default()
+    }
+
+    /// Sets the name of the original creator of the message.
+    pub fn sender(mut self, sender: XorName) -> MpidMessageBuilder {
+        self.sender = Some(sender);
+        self
+    }
+
+    /// Sets the name of the intended receiver of the message.
+    pub fn recipient(mut self, recipient: XorName) -> MpidMessageBuilder {
+        self.recipient = Some(recipient);
+        self
+    }
+
+    /// Sets the header's arbitrary, user-supplied information.
+    pub fn metadata(mut self, metadata: Vec<u8>) -> MpidMessageBuilder {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Sets the main portion of the message.
+    pub fn body(mut self, body: Vec<u8>) -> MpidMessageBuilder {
+        self.body = body;
+        self
+    }
+
+    /// Sets the header's GUID.  See
+    /// [MpidHeader::with_guid()](struct.MpidHeader.html#method.with_guid).
+    pub fn guid(mut self, guid: [u8; GUID_SIZE]) -> MpidMessageBuilder {
+        self.guid = Some(guid);
+        self
+    }
+
+    /// Sets the time, in seconds since the Unix epoch, after which the message may be discarded.
+    pub fn expiry(mut self, expiry: u64) -> MpidMessageBuilder {
+        self.expiry = Some(expiry);
+        self
+    }
+
+    /// Sets the creation timestamp of the header, in seconds since the Unix epoch.  Defaults to the
+    /// time at which the message is built.
+    pub fn created(mut self, created: u64) -> MpidMessageBuilder {
+        self.created = Some(created);
+        self
+    }
+
+    /// Sets the priority of the message.
+    pub fn priority(mut self, priority: Priority) -> MpidMessageBuilder {
+        self.priority = priority;
+        self
+    }
+
+    /// Sets the name to which replies to the message should be addressed.
+    pub fn reply_to(mut self, reply_to: XorName) -> MpidMessageBuilder {
+        self.reply_to = Some(reply_to);
+        self
+    }
+
+    /// Sets the codec with which the body is compressed, e.g. as agreed with the recipient via
+    /// [Codec::negotiate()](enum.Codec.html#method.negotiate).  Compression is skipped if it
+    /// doesn't make the body smaller.  The recipient recovers the body via
+    /// [MpidMessage::body()](struct.MpidMessage.html#method.body), or
+    /// [MpidMessage::open()](struct.MpidMessage.html#method.open) if it's encrypted.
+    pub fn compress(mut self, codec: Codec) -> MpidMessageBuilder {
+        self.codec = codec;
+        self
+    }
+
+    /// Pads the metadata and body so that MpidManagers only learn which of a fixed set of size
+    /// classes their lengths fall into.
+    ///
+    /// After any compression, the body is prefixed with its length and padded with zeros up to the
+    /// smallest class which fits: [`MIN_BODY_SIZE_CLASS`](constant.MIN_BODY_SIZE_CLASS.html) times
+    /// a power of four, or the maximum body size.  Unencrypted metadata is padded likewise, from
+    /// [`MIN_METADATA_SIZE_CLASS`](constant.MIN_METADATA_SIZE_CLASS.html) up to
+    /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html), while encrypted
+    /// metadata is always padded to a single size.  Padding is applied before encryption.  It's
+    /// removed by
+    /// [MpidHeader::metadata()](struct.MpidHeader.html#method.metadata) and
+    /// [MpidMessage::body()](struct.MpidMessage.html#method.body), or
+    /// by their counterparts for encrypted messages.
+    pub fn pad(mut self) -> MpidMessageBuilder {
+        self.padded = true;
+        self
+    }
+
+    /// Sets the recipient to `recipient.name()` and encrypts the metadata and body to `recipient`'s
+    /// encryption key.  See
+    /// [MpidMessage::new_encrypted()](struct.MpidMessage.html#method.new_encrypted).
+    pub fn encrypt_to(mut self, recipient: &MpidPublicId) -> MpidMessageBuilder {
+        self.recipient = Some(recipient.name());
+        self.encryption_key = Some(*recipient.encryption_key());
+        self
+    }
+
+    /// Returns every constraint violated by the fields set so far, or an empty vector if the
+    /// message can be built.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut violations = vec![];
+        if self.sender.is_none() {
+            violations.push(ValidationError::MissingSender);
+        }
+        if self.recipient.is_none() {
+            violations.push(ValidationError::MissingRecipient);
+        }
+        let padding_overhead = if self.padded {
+            PADDING_OVERHEAD
+        } else {
+            0
+        };
+        let max_metadata_size = if self.encryption_key.is_some() {
+            MAX_ENCRYPTED_METADATA_SIZE
+        } else {
+            MAX_HEADER_METADATA_SIZE - padding_overhead
+        };
+        if self.metadata.len() > max_metadata_size {
+            violations.push(ValidationError::MetadataTooLarge);
+        }
+        let max_body_size = if self.codec != Codec::None {
+            MAX_DECOMPRESSED_BODY_SIZE
+        } else if self.encryption_key.is_some() {
+            MAX_ENCRYPTED_BODY_SIZE - padding_overhead
+        } else {
+            MAX_BODY_SIZE - padding_overhead
+        };
+        if self.body.len() > max_body_size {
+            violations.push(ValidationError::BodyTooLarge);
+        }
+        if let Some(expiry) = self.expiry {
+            if expiry <= messaging::seconds_since_epoch() {
+                violations.push(ValidationError::ExpiryInPast);
+            }
+        }
+        violations
+    }
+
+    /// Validates the fields and builds a message signed by `signer`.
+    ///
+    /// If any constraint is violated, `Error::Validation` listing all of them is returned.
+    /// Otherwise an error is only returned if a compressed body still exceeds its size limit, if
+    /// serialisation during the signing process fails or if `signer` fails.
+    pub fn build<S: Signer + ?Sized>(self, signer: &S) -> Result<MpidMessage, Error> {
+        let violations = self.validate();
+        if !violations.is_empty() {
+            return Err(Error::Validation(violations));
+        }
+        let (sender, recipient) = match (self.sender, self.recipient) {
+            (Some(sender), Some(recipient)) => (sender, recipient),
+            _ => unreachable!("validate() rejects a missing sender or recipient"),
+        };
+        let guid = self.guid.unwrap_or_else(|| mpid_header::random_guid(&mut rand::thread_rng()));
+        let metadata = match self.encryption_key {
+            Some(ref encryption_key) => mpid_header::seal_metadata(&self.metadata, encryption_key),
+            None if self.padded => {
+                try!(SizeClasses::metadata().pad(&self.metadata).ok_or(Error::MetadataTooLarge))
+            }
+            None => self.metadata,
+        };
+        let created = self.created.unwrap_or_else(messaging::seconds_since_epoch);
+        let mut header_detail = mpid_header::Detail::new(sender, metadata, guid, created);
+        header_detail.padded = self.padded;
+        header_detail.expiry = self.expiry;
+        header_detail.priority = self.priority;
+        header_detail.reply_to = self.reply_to;
+        let mut body = self.body;
+        if self.codec != Codec::None {
+            let compressed = try!(self.codec.compress(&body));
+            if compressed.len() < body.len() {
+                header_detail.codec = self.codec;
+                body = compressed;
+            }
+        }
+        if self.padded {
+            let classes = SizeClasses::body(self.encryption_key.is_some());
+            body = try!(classes.pad(&body).ok_or(Error::BodyTooLarge));
+        }
+        let body = match self.encryption_key {
+            Some(ref encryption_key) => {
+                header_detail.encrypted_metadata = true;
+                header_detail.encrypted_body = true;
+                if body.len() > MAX_ENCRYPTED_BODY_SIZE {
+                    return Err(Error::BodyTooLarge);
+                }
+                mpid_message::seal_body(&body, encryption_key)
+            }
+            None => body,
+        };
+        mpid_message::new_message(header_detail, recipient, body, signer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maidsafe_utilities::serialisation::serialise;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{self, Codec, Error, MIN_BODY_SIZE_CLASS, MIN_METADATA_SIZE_CLASS, MpidMessage,
+                    MpidPublicId, Priority};
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let reply_to: XorName = rand::random();
+        let expiry = messaging::seconds_since_epoch() + 3600;
+
+        // Check the defaults.
+        let guid = [7u8; GUID_SIZE];
+        let builder = MpidMessageBuilder::new()
+                          .sender(sender)
+                          .recipient(recipient)
+                          .metadata(vec![1])
+                          .body(vec![2])
+                          .guid(guid);
+        let before = messaging::seconds_since_epoch();
+        let message = unwrap_result!(builder.clone().build(&secret_key));
+        let after = messaging::seconds_since_epoch();
+        assert!(message.verify(&public_key));
+        assert_eq!(*message.header().sender(), sender);
+        assert_eq!(*message.recipient(), recipient);
+        assert_eq!(unwrap_result!(message.header().metadata()), vec![1]);
+        assert_eq!(unwrap_result!(message.body()), vec![2]);
+        assert_eq!(*message.header().guid(), guid);
+        assert_eq!(message.header().priority(), Priority::Normal);
+        assert!(message.header().expiry().is_none());
+        assert!(message.header().reply_to().is_none());
+        let created = message.header().created();
+        assert!(before <= created && created <= after);
+
+        // Building is deterministic given the GUID and creation timestamp.
+        let builder = builder.created(created);
+        assert_eq!(unwrap_result!(builder.clone().build(&secret_key)), message);
+        assert_eq!(unwrap_result!(builder.build(&secret_key)), message);
+
+        // Optional fields are carried in the signed header.
+        let message = unwrap_result!(MpidMessageBuilder::new()
+                                         .sender(sender)
+                                         .recipient(recipient)
+                                         .expiry(expiry)
+                                         .priority(Priority::High)
+                                         .reply_to(reply_to)
+                                         .build(&secret_key));
+        assert!(message.verify(&public_key));
+        unwrap_result!(message.validate());
+        assert_eq!(message.header().expiry(), Some(expiry));
+        assert_eq!(message.header().priority(), Priority::High);
+        assert_eq!(message.header().reply_to(), Some(&reply_to));
+        let serialised = unwrap_result!(serialise(&message));
+        assert_eq!(unwrap_result!(MpidMessage::deserialise(&serialised)), message);
+
+        // All violations are reported together.
+        let metadata = messaging::generate_random_bytes(MAX_HEADER_METADATA_SIZE + 1);
+        let body = messaging::generate_random_bytes(MAX_BODY_SIZE + 1);
+        let builder = MpidMessageBuilder::new().metadata(metadata).body(body).expiry(0);
+        let expected = vec![ValidationError::MissingSender,
+                            ValidationError::MissingRecipient,
+                            ValidationError::MetadataTooLarge,
+                            ValidationError::BodyTooLarge,
+                            ValidationError::ExpiryInPast];
+        assert_eq!(builder.validate(), expected);
+        match builder.build(&secret_key) {
+            Err(Error::Validation(violations)) => assert_eq!(violations, expected),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Encrypted metadata and bodies are subject to lower size limits.
+        let (encryption_key, decryption_key) = box_::gen_keypair();
+        let recipient_id = MpidPublicId::new(public_key, encryption_key);
+        let body = messaging::generate_random_bytes(MAX_ENCRYPTED_BODY_SIZE + 1);
+        let metadata = messaging::generate_random_bytes(MAX_ENCRYPTED_METADATA_SIZE + 1);
+        let builder = MpidMessageBuilder::new()
+                          .sender(sender)
+                          .encrypt_to(&recipient_id)
+                          .metadata(metadata)
+                          .body(body);
+        assert_eq!(builder.validate(),
+                   vec![ValidationError::MetadataTooLarge, ValidationError::BodyTooLarge]);
+        let message = unwrap_result!(builder.metadata(vec![4]).body(vec![3]).build(&secret_key));
+        assert!(message.is_encrypted());
+        assert_eq!(unwrap_result!(message.header().open_metadata(&encryption_key,
+                                                                 &decryption_key)),
+                   vec![4]);
+        assert_eq!(*message.recipient(), recipient_id.name());
+        assert_eq!(unwrap_result!(message.open(&encryption_key, &decryption_key)), vec![3]);
+
+        // Compressed bodies are subject to the size limit once compressed, and are decompressed on
+        // request.
+        let text = b"Lorem ipsum dolor sit amet. ".iter()
+                                                 .cycle()
+                                                 .take(2 * MAX_BODY_SIZE)
+                                                 .cloned()
+                                                 .collect::<Vec<u8>>();
+        let builder = MpidMessageBuilder::new()
+                          .sender(sender)
+                          .recipient(recipient)
+                          .body(text.clone());
+        assert_eq!(builder.validate(), vec![ValidationError::BodyTooLarge]);
+        let message = unwrap_result!(builder.clone().compress(Codec::Deflate).build(&secret_key));
+        assert!(message.verify(&public_key));
+        assert_eq!(message.header().codec(), Codec::Deflate);
+        assert!(message.raw_body().len() < MAX_BODY_SIZE / 10);
+        assert_eq!(unwrap_result!(message.body()), text);
+        let serialised = unwrap_result!(serialise(&message));
+        assert!(serialised.len() < MAX_BODY_SIZE / 10);
+        assert_eq!(unwrap_result!(MpidMessage::deserialise(&serialised)), message);
+        let message = unwrap_result!(builder.clone()
+                                            .encrypt_to(&recipient_id)
+                                            .compress(Codec::Deflate)
+                                            .build(&secret_key));
+        assert_eq!(message.header().codec(), Codec::Deflate);
+        assert_eq!(unwrap_result!(message.open(&encryption_key, &decryption_key)), text);
+
+        // Compression is skipped where it doesn't help, and bodies which remain too large once
+        // compressed are rejected.
+        let random = messaging::generate_random_bytes(1000);
+        let message = unwrap_result!(builder.clone()
+                                            .body(random.clone())
+                                            .compress(Codec::Deflate)
+                                            .build(&secret_key));
+        assert_eq!(message.header().codec(), Codec::None);
+        assert_eq!(*message.raw_body(), random);
+        let random = messaging::generate_random_bytes(MAX_BODY_SIZE + 1);
+        match builder.body(random).compress(Codec::Deflate).build(&secret_key) {
+            Err(Error::BodyTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn padded() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let (encryption_key, decryption_key) = box_::gen_keypair();
+        let recipient_id = MpidPublicId::new(public_key, encryption_key);
+        let text = b"Lorem ipsum dolor sit amet. ".iter()
+                                                 .cycle()
+                                                 .take(2 * MAX_BODY_SIZE)
+                                                 .cloned()
+                                                 .collect::<Vec<u8>>();
+
+        // Padded metadata and bodies of different lengths within a size class are
+        // indistinguishable, and the padding is removed on request.
+        let builder = MpidMessageBuilder::new().sender(sender).recipient(recipient).pad();
+        let short = unwrap_result!(builder.clone()
+                                          .metadata(vec![1])
+                                          .body(vec![2; 300])
+                                          .build(&secret_key));
+        let long = unwrap_result!(builder.clone()
+                                         .metadata(vec![1; 20])
+                                         .body(vec![2; 1000])
+                                         .build(&secret_key));
+        for &(ref message, metadata_size, body_size) in &[(&short, 1, 300), (&long, 20, 1000)] {
+            assert!(message.verify(&public_key));
+            assert!(message.header().is_padded());
+            assert_eq!(message.header().raw_metadata().len(), MIN_METADATA_SIZE_CLASS);
+            assert_eq!(unwrap_result!(message.header().metadata()),
+                       vec![1; metadata_size]);
+            assert_eq!(message.raw_body().len(), 4 * MIN_BODY_SIZE_CLASS);
+            assert_eq!(unwrap_result!(message.body()), vec![2; body_size]);
+            let serialised = unwrap_result!(serialise(message));
+            assert_eq!(unwrap_result!(MpidMessage::deserialise(&serialised)), **message);
+        }
+        let message = unwrap_result!(builder.clone()
+                                            .body(text.clone())
+                                            .compress(Codec::Deflate)
+                                            .build(&secret_key));
+        assert!([1, 4, 16].iter().any(|&factor| {
+            message.raw_body().len() == factor * MIN_BODY_SIZE_CLASS
+        }));
+        assert_eq!(unwrap_result!(message.body()), text);
+
+        // Encrypted bodies are padded before encryption.
+        let message = unwrap_result!(builder.clone()
+                                            .encrypt_to(&recipient_id)
+                                            .metadata(vec![1])
+                                            .body(vec![2])
+                                            .build(&secret_key));
+        assert_eq!(message.raw_body().len(),
+                   MIN_BODY_SIZE_CLASS + MAX_BODY_SIZE - MAX_ENCRYPTED_BODY_SIZE);
+        assert_eq!(unwrap_result!(message.header().open_metadata(&encryption_key,
+                                                                 &decryption_key)),
+                   vec![1]);
+        assert_eq!(unwrap_result!(message.open(&encryption_key, &decryption_key)), vec![2]);
+
+        // Padding reduces the size limits.
+        let builder = builder.metadata(vec![0; MAX_HEADER_METADATA_SIZE - PADDING_OVERHEAD])
+                             .body(vec![0; MAX_BODY_SIZE - PADDING_OVERHEAD]);
+        assert!(builder.validate().is_empty());
+        let message = unwrap_result!(builder.clone().build(&secret_key));
+        assert_eq!(message.header().raw_metadata().len(), MAX_HEADER_METADATA_SIZE);
+        assert_eq!(message.raw_body().len(), MAX_BODY_SIZE);
+        let builder = builder.metadata(vec![0; MAX_HEADER_METADATA_SIZE - PADDING_OVERHEAD + 1])
+                             .body(vec![0; MAX_BODY_SIZE - PADDING_OVERHEAD + 1]);
+        assert_eq!(builder.validate(),
+                   vec![ValidationError::MetadataTooLarge, ValidationError::BodyTooLarge]);
+
+        // Invalid padding is reported as malformed.
+        let mut detail = mpid_header::Detail::new(sender,
+                                                  vec![0xff; MIN_METADATA_SIZE_CLASS],
+                                                  [0; GUID_SIZE],
+                                                  0);
+        detail.padded = true;
+        let message = unwrap_result!(mpid_message::new_message(detail,
+                                                               recipient,
+                                                               vec![0xff; MIN_BODY_SIZE_CLASS],
+                                                               &secret_key));
+        match message.header().metadata() {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        match message.body() {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -18,7 +18,15 @@
 // use maidsafe_utilities::serialisation::serialise;
 // use sodiumoxide::crypto::hash::sha512;
 // use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
-use super::{MpidHeader, MpidMessage};
+/// Maximum allowed length of a serialised wrapper (2 MiB), as accepted by
+/// [`MpidMessageWrapper::deserialise()`](enum.MpidMessageWrapper.html#method.deserialise).
+/// Listings of more headers than fit in this should be retrieved a page at a time.
+pub const MAX_SERIALISED_WRAPPER_SIZE: usize = 1 << 21;
+
+use client_errors::MessagingError;
+use messaging;
+use super::{CachingKeyResolver, Error, HeaderPage, KeyResolver, MAX_HEADER_PAGE_SIZE, MpidHeader,
+            MpidMessage};
 use xor_name::XorName;
 
 /// A serialisable wrapper to allow multiplexing all MPID message types and actions via a single
@@ -42,15 +50,218 @@ pub enum MpidMessageWrapper {
     /// Sent by MpidManagers to the Client as a response to an `OutboxHas`.  The contents is a
     /// subset of the list provided in the corresponding `OutboxHas`.
     OutboxHasResponse(Vec<MpidHeader>),
-    /// Sent by a Client to its MpidManagers to retrieve the list of headers of all messages in its
-    /// outbox.
+    /// Sent by a Client to its MpidManagers to retrieve the list of headers of messages in its
+    /// outbox.  Superseded by `GetOutboxHeadersPage`, as only the first page is returned.
     GetOutboxHeaders,
     /// Sent by MpidManagers to the Client as a response to a `GetOutboxHeaders`.  The contents is
-    /// the list of headers of all messages in the outbox.
+    /// the list of headers of the first
+    /// [`MAX_HEADER_PAGE_SIZE`](constant.MAX_HEADER_PAGE_SIZE.html) messages in the outbox,
+    /// ordered by header name.
     GetOutboxHeadersResponse(Vec<MpidHeader>),
     /// Sent by a Client to its MpidManagers to delete the named message from its inbox or outbox.
     DeleteMessage(XorName),
     /// Sent by a receiving Client to the sender's MpidManagers to delete the named message's header
     /// from the sender's outbox.
     DeleteHeader(XorName),
+    /// Sent by a Client to its MpidManagers to query whether the provided vector of header names
+    /// continue to exist as headers in its inbox.
+    InboxHas(Vec<XorName>),
+    /// Sent by MpidManagers to the Client as a response to an `InboxHas`.  The contents is a subset
+    /// of the list provided in the corresponding `InboxHas`.
+    InboxHasResponse(Vec<MpidHeader>),
+    /// Sent by a Client to its MpidManagers to retrieve the list of headers in its inbox.
+    /// Superseded by `GetInboxHeadersPage`, as only the first page is returned.
+    GetInboxHeaders,
+    /// Sent by MpidManagers to the Client as a response to a `GetInboxHeaders`.  The contents is
+    /// the list of the first [`MAX_HEADER_PAGE_SIZE`](constant.MAX_HEADER_PAGE_SIZE.html)
+    /// headers in the inbox, ordered by header name.
+    GetInboxHeadersResponse(Vec<MpidHeader>),
+    /// Sent by a Client to its MpidManagers to retrieve one page of the headers of messages in its
+    /// outbox, ordered by header name.  The first field is the cursor: the name after which the
+    /// page starts, or `None` to start from the beginning.  The second is the maximum number of
+    /// headers to return, capped at [`MAX_HEADER_PAGE_SIZE`](constant.MAX_HEADER_PAGE_SIZE.html).
+    GetOutboxHeadersPage(Option<XorName>, u32),
+    /// Sent by MpidManagers to the Client as a response to a `GetOutboxHeadersPage`.
+    GetOutboxHeadersPageResponse(HeaderPage),
+    /// Sent by a Client to its MpidManagers to retrieve one page of the headers in its inbox, with
+    /// the same semantics as `GetOutboxHeadersPage`.
+    GetInboxHeadersPage(Option<XorName>, u32),
+    /// Sent by MpidManagers to the Client as a response to a `GetInboxHeadersPage`.
+    GetInboxHeadersPageResponse(HeaderPage),
+    /// Sent by MpidManagers when the named message couldn't be stored.  Sent by the recipient's
+    /// MpidManagers to the sender's in response to a `PutHeader`, and by the sender's MpidManagers
+    /// to the Client in response to a `PutMessage` or on receipt of such a failure.
+    PutMessageFailure(XorName, MessagingError),
+    /// Sent by MpidManagers when the named message couldn't be retrieved.  Sent by the sender's
+    /// MpidManagers to the recipient's in response to a `GetMessage`, and by the recipient's
+    /// MpidManagers to the Client in response to a `GetMessage` or on receipt of such a failure.
+    GetMessageFailure(XorName, MessagingError),
+    /// Sent by MpidManagers to the Client in response to a `DeleteMessage` which failed.
+    DeleteMessageFailure(XorName, MessagingError),
+    /// Sent by MpidManagers which have purged the named message on its expiry to the MpidManagers
+    /// holding its counterpart: the recipient's for a message from the outbox, and the sender's
+    /// for a header from the inbox.
+    MessageExpired(XorName),
+}
+
+impl MpidMessageWrapper {
+    /// Deserialises a wrapper received from an untrusted source.
+    ///
+    /// Unlike deserialising via `RustcDecodable`, input longer than
+    /// [`MAX_SERIALISED_WRAPPER_SIZE`](constant.MAX_SERIALISED_WRAPPER_SIZE.html) is rejected
+    /// before decoding, and the decoded wrapper is checked via [`validate()`](#method.validate).
+    pub fn deserialise(serialised: &[u8]) -> Result<MpidMessageWrapper, Error> {
+        let wrapper: MpidMessageWrapper =
+            try!(messaging::deserialise_bounded(serialised, MAX_SERIALISED_WRAPPER_SIZE));
+        try!(wrapper.validate());
+        Ok(wrapper)
+    }
+
+    /// Checks the invariants of every header, message and page held by the wrapper.
+    pub fn validate(&self) -> Result<(), Error> {
+        match *self {
+            MpidMessageWrapper::PutMessage(ref message) => message.validate(),
+            MpidMessageWrapper::PutHeader(ref header) |
+            MpidMessageWrapper::GetMessage(ref header) => header.validate(),
+            MpidMessageWrapper::OutboxHasResponse(ref headers) |
+            MpidMessageWrapper::InboxHasResponse(ref headers) => {
+                for header in headers {
+                    try!(header.validate());
+                }
+                Ok(())
+            }
+            MpidMessageWrapper::GetOutboxHeadersResponse(ref headers) |
+            MpidMessageWrapper::GetInboxHeadersResponse(ref headers) => {
+                if headers.len() > MAX_HEADER_PAGE_SIZE {
+                    return Err(Error::Malformed);
+                }
+                for header in headers {
+                    try!(header.validate());
+                }
+                Ok(())
+            }
+            MpidMessageWrapper::GetOutboxHeadersPageResponse(ref page) |
+            MpidMessageWrapper::GetInboxHeadersPageResponse(ref page) => {
+                if page.headers.len() > MAX_HEADER_PAGE_SIZE {
+                    return Err(Error::Malformed);
+                }
+                for header in &page.headers {
+                    try!(header.validate());
+                }
+                Ok(())
+            }
+            MpidMessageWrapper::Online |
+            MpidMessageWrapper::OutboxHas(_) |
+            MpidMessageWrapper::GetOutboxHeaders |
+            MpidMessageWrapper::DeleteMessage(_) |
+            MpidMessageWrapper::DeleteHeader(_) |
+            MpidMessageWrapper::InboxHas(_) |
+            MpidMessageWrapper::GetInboxHeaders |
+            MpidMessageWrapper::GetOutboxHeadersPage(..) |
+            MpidMessageWrapper::GetInboxHeadersPage(..) |
+            MpidMessageWrapper::PutMessageFailure(..) |
+            MpidMessageWrapper::GetMessageFailure(..) |
+            MpidMessageWrapper::DeleteMessageFailure(..) |
+            MpidMessageWrapper::MessageExpired(_) => Ok(()),
+        }
+    }
+
+    /// Validates the signatures of every header and message carried by the wrapper against the
+    /// keys of their senders, as looked up via `resolver`.  Wrappers carrying no signed data
+    /// trivially pass.
+    pub fn verify_with_resolver<K: KeyResolver + ?Sized>(&self, resolver: &K) -> bool {
+        match *self {
+            MpidMessageWrapper::PutMessage(ref message) => message.verify_with_resolver(resolver),
+            MpidMessageWrapper::PutHeader(ref header) |
+            MpidMessageWrapper::GetMessage(ref header) => header.verify_with_resolver(resolver),
+            MpidMessageWrapper::OutboxHasResponse(ref headers) |
+            MpidMessageWrapper::GetOutboxHeadersResponse(ref headers) |
+            MpidMessageWrapper::InboxHasResponse(ref headers) |
+            MpidMessageWrapper::GetInboxHeadersResponse(ref headers) => {
+                headers.iter().all(|header| header.verify_with_resolver(resolver))
+            }
+            MpidMessageWrapper::GetOutboxHeadersPageResponse(ref page) |
+            MpidMessageWrapper::GetInboxHeadersPageResponse(ref page) => {
+                page.headers.iter().all(|header| header.verify_with_resolver(resolver))
+            }
+            MpidMessageWrapper::Online |
+            MpidMessageWrapper::OutboxHas(_) |
+            MpidMessageWrapper::GetOutboxHeaders |
+            MpidMessageWrapper::DeleteMessage(_) |
+            MpidMessageWrapper::DeleteHeader(_) |
+            MpidMessageWrapper::InboxHas(_) |
+            MpidMessageWrapper::GetInboxHeaders |
+            MpidMessageWrapper::GetOutboxHeadersPage(..) |
+            MpidMessageWrapper::GetInboxHeadersPage(..) |
+            MpidMessageWrapper::PutMessageFailure(..) |
+            MpidMessageWrapper::GetMessageFailure(..) |
+            MpidMessageWrapper::DeleteMessageFailure(..) |
+            MpidMessageWrapper::MessageExpired(_) => true,
+        }
+    }
+
+    /// Applies [`verify_with_resolver()`](#method.verify_with_resolver) to each of `wrappers`,
+    /// returning the individual results.  The keys of each sender are only looked up once.
+    pub fn verify_batch_with_resolver<K: KeyResolver + ?Sized>(wrappers: &[MpidMessageWrapper],
+                                                               resolver: &K)
+                                                               -> Vec<bool> {
+        let resolver = CachingKeyResolver::with_empty_results(resolver, usize::max_value());
+        wrappers.iter().map(|wrapper| wrapper.verify_with_resolver(&resolver)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maidsafe_utilities::serialisation::serialise;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{self, Error, MAX_BODY_SIZE, MAX_SERIALISED_MESSAGE_SIZE, MpidHeader,
+                    MpidMessage};
+
+    #[test]
+    fn deserialise() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let body = messaging::generate_random_bytes(MAX_BODY_SIZE);
+        let message = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                      vec![],
+                                                      rand::random(),
+                                                      body,
+                                                      &secret_key));
+        let serialised_message = unwrap_result!(serialise(&message));
+        assert!(serialised_message.len() <= MAX_SERIALISED_MESSAGE_SIZE);
+        assert_eq!(unwrap_result!(MpidMessage::deserialise(&serialised_message)), message);
+        let wrapper = MpidMessageWrapper::PutMessage(message.clone());
+        let serialised = unwrap_result!(serialise(&wrapper));
+        assert_eq!(unwrap_result!(MpidMessageWrapper::deserialise(&serialised)), wrapper);
+
+        // Trailing, truncated and oversized input is rejected.
+        let mut extended = serialised_message.clone();
+        extended.push(0);
+        match MpidMessage::deserialise(&extended) {
+            Err(Error::Malformed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(MpidMessage::deserialise(&serialised_message[..100]).is_err());
+        extended.resize(MAX_SERIALISED_MESSAGE_SIZE + 1, 0);
+        match MpidMessage::deserialise(&extended) {
+            Err(Error::InputTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // A length prefix claiming more data than the input holds fails without reading further.
+        let header = unwrap_result!(MpidHeader::new(sender, vec![1; 8], &secret_key));
+        let mut serialised_header = unwrap_result!(serialise(&header));
+        assert_eq!(unwrap_result!(MpidHeader::deserialise(&serialised_header)), header);
+        // The metadata length directly follows the sender and GUID.
+        let offset = unwrap_result!(serialise(header.sender())).len() +
+                     unwrap_result!(serialise(header.guid())).len();
+        for byte in &mut serialised_header[offset..offset + 4] {
+            *byte = 255;
+        }
+        assert!(MpidHeader::deserialise(&serialised_header).is_err());
+        assert!(MpidMessageWrapper::deserialise(&[255; 16]).is_err());
+    }
 }
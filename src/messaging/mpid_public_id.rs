@@ -0,0 +1,105 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::hash::sha512;
+use sodiumoxide::crypto::sign::{self, Signature};
+use super::signing::Verifier;
+use xor_name::XorName;
+
+/// The public identity of an MPID account, comprising its public signing and encryption keys.
+///
+/// The account's name is derived from the keys, so a header or message claiming to be from a given
+/// sender can be checked against that sender's `MpidPublicId` without any separate key lookup; see
+/// [`MpidHeader::verify_from_sender()`](struct.MpidHeader.html#method.verify_from_sender).
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct MpidPublicId {
+    signing_key: sign::PublicKey,
+    encryption_key: box_::PublicKey,
+}
+
+impl MpidPublicId {
+    /// Constructor.
+    pub fn new(signing_key: sign::PublicKey, encryption_key: box_::PublicKey) -> MpidPublicId {
+        MpidPublicId {
+            signing_key: signing_key,
+            encryption_key: encryption_key,
+        }
+    }
+
+    /// The public key used to verify the account's signatures.
+    pub fn signing_key(&self) -> &sign::PublicKey {
+        &self.signing_key
+    }
+
+    /// The public key used to encrypt data for the account.
+    pub fn encryption_key(&self) -> &box_::PublicKey {
+        &self.encryption_key
+    }
+
+    /// The account's name: the SHA512 hash of the signing key followed by the encryption key.
+    pub fn name(&self) -> XorName {
+        let mut keys = Vec::with_capacity(sign::PUBLICKEYBYTES + box_::PUBLICKEYBYTES);
+        keys.extend_from_slice(&self.signing_key.0);
+        keys.extend_from_slice(&self.encryption_key.0);
+        XorName(sha512::hash(&keys).0)
+    }
+}
+
+impl Verifier for MpidPublicId {
+    fn verify(&self, signature: &Signature, data: &[u8]) -> bool {
+        self.signing_key.verify(signature, data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use messaging::{MpidHeader, MpidMessage};
+    use rand;
+    use sodiumoxide::crypto::{box_, sign};
+    use xor_name::XorName;
+
+    #[test]
+    fn full() {
+        let (signing_key, secret_key) = sign::gen_keypair();
+        let (encryption_key, _) = box_::gen_keypair();
+        let public_id = MpidPublicId::new(signing_key, encryption_key);
+        assert_eq!(public_id.name(), MpidPublicId::new(signing_key, encryption_key).name());
+        let (other_encryption_key, _) = box_::gen_keypair();
+        assert!(public_id.name() != MpidPublicId::new(signing_key, other_encryption_key).name());
+
+        // Only headers and messages naming the identity as their sender pass.
+        let recipient: XorName = rand::random();
+        let header = unwrap_result!(MpidHeader::new(public_id.name(), vec![], &secret_key));
+        assert!(header.verify_from_sender(&public_id));
+        let message = unwrap_result!(MpidMessage::new(public_id.name(),
+                                                      vec![],
+                                                      recipient,
+                                                      vec![1],
+                                                      &secret_key));
+        assert!(message.verify_from_sender(&public_id));
+
+        // A validly signed header naming another sender fails, as does one signed by another key.
+        let impostor = unwrap_result!(MpidHeader::new(rand::random(), vec![], &secret_key));
+        assert!(impostor.verify(&signing_key));
+        assert!(!impostor.verify_from_sender(&public_id));
+        let (_, other_secret_key) = sign::gen_keypair();
+        let forged = unwrap_result!(MpidHeader::new(public_id.name(), vec![], &other_secret_key));
+        assert!(!forged.verify_from_sender(&public_id));
+    }
+}
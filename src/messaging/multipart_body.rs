@@ -0,0 +1,239 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{AttachmentRef, Error, MAX_BODY_SIZE};
+
+/// Version of the [`MultipartBody`](struct.MultipartBody.html) encoding, written as its first
+/// byte.
+pub const MULTIPART_VERSION: u8 = 1;
+
+const TEXT_CONTENT_TYPE: &'static str = "text/plain; charset=utf-8";
+const HTML_CONTENT_TYPE: &'static str = "text/html; charset=utf-8";
+
+/// The content of a [`BodyPart`](struct.BodyPart.html).
+#[derive(PartialEq, Eq, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub enum PartContent {
+    /// Plain text.
+    Text(String),
+    /// HTML.
+    Html(String),
+    /// Arbitrary binary data, e.g. an attachment small enough to be sent inline.
+    Binary(Vec<u8>),
+    /// A reference to data stored elsewhere on the network.
+    Reference(AttachmentRef),
+}
+
+/// A single part of a [`MultipartBody`](struct.MultipartBody.html).
+#[derive(PartialEq, Eq, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct BodyPart {
+    /// The type of the content, e.g. a MIME type.  It must be non-empty printable ASCII.
+    pub content_type: String,
+    /// The name of the part, e.g. the file name of an attachment.  It mustn't contain control
+    /// characters or path separators.
+    pub name: Option<String>,
+    /// The content of the part.
+    pub content: PartContent,
+}
+
+impl BodyPart {
+    /// Constructs an unnamed plain text part.
+    pub fn text(text: String) -> BodyPart {
+        BodyPart {
+            content_type: TEXT_CONTENT_TYPE.to_owned(),
+            name: None,
+            content: PartContent::Text(text),
+        }
+    }
+
+    /// Constructs an unnamed HTML part.
+    pub fn html(html: String) -> BodyPart {
+        BodyPart {
+            content_type: HTML_CONTENT_TYPE.to_owned(),
+            name: None,
+            content: PartContent::Html(html),
+        }
+    }
+
+    /// Constructs a binary attachment part.
+    pub fn binary(content_type: String, name: Option<String>, data: Vec<u8>) -> BodyPart {
+        BodyPart {
+            content_type: content_type,
+            name: name,
+            content: PartContent::Binary(data),
+        }
+    }
+
+    /// Constructs a part referring to data stored elsewhere on the network.
+    pub fn reference(content_type: String,
+                     name: Option<String>,
+                     reference: AttachmentRef)
+                     -> BodyPart {
+        BodyPart {
+            content_type: content_type,
+            name: name,
+            content: PartContent::Reference(reference),
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        let name_is_valid = |name: &String| {
+            name.chars().all(|character| !character.is_control() && character != '/' &&
+                                         character != '\\')
+        };
+        !self.content_type.is_empty() &&
+        self.content_type.bytes().all(|byte| byte >= 0x20 && byte < 0x7f) &&
+        self.name.as_ref().map_or(true, name_is_valid)
+    }
+}
+
+/// A message body made up of typed parts, such as alternative text and HTML renderings of the
+/// message plus its attachments.
+///
+/// The encoding is the version byte followed by the serialised parts.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct MultipartBody {
+    /// The parts, in order.
+    pub parts: Vec<BodyPart>,
+}
+
+impl MultipartBody {
+    /// Constructs a body with no parts.
+    pub fn new() -> MultipartBody {
+        Default::default()
+    }
+
+    /// Encodes the parts such that they fit within [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
+    /// See [`encode_within()`](#method.encode_within).
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        self.encode_within(MAX_BODY_SIZE)
+    }
+
+    /// Encodes the parts, e.g. with a `limit` of
+    /// [`MAX_ENCRYPTED_BODY_SIZE`](constant.MAX_ENCRYPTED_BODY_SIZE.html) for an encrypted
+    /// message.
+    ///
+    /// An error will be returned if any part's content type or name is invalid, or if the encoded
+    /// body would exceed `limit` bytes.
+    pub fn encode_within(&self, limit: usize) -> Result<Vec<u8>, Error> {
+        if !self.parts.iter().all(BodyPart::is_valid) {
+            return Err(Error::Malformed);
+        }
+        super::serialise_versioned(MULTIPART_VERSION, &self.parts, limit)
+    }
+
+    /// Decodes a message body produced by [`encode()`](#method.encode).
+    ///
+    /// An error will be returned if `body` exceeds `MAX_BODY_SIZE`, has an unknown version, isn't
+    /// exactly an encoded list of parts or has a part with an invalid content type or name.
+    pub fn decode(body: &[u8]) -> Result<MultipartBody, Error> {
+        let parts: Vec<BodyPart> = try!(super::deserialise_versioned(MULTIPART_VERSION,
+                                                                     body,
+                                                                     MAX_BODY_SIZE));
+        if !parts.iter().all(BodyPart::is_valid) {
+            return Err(Error::Malformed);
+        }
+        Ok(MultipartBody { parts: parts })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use messaging::{self, AttachmentRef, Error, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE};
+
+    #[test]
+    fn full() {
+        let data = messaging::generate_random_bytes(100);
+        let reference = AttachmentRef::new(rand::random(), &data);
+        let mut body = MultipartBody::new();
+        body.parts.push(BodyPart::text("Hello".to_owned()));
+        body.parts.push(BodyPart::html("<p>Hello</p>".to_owned()));
+        body.parts.push(BodyPart::binary("image/png".to_owned(),
+                                         Some("inline.png".to_owned()),
+                                         data));
+        body.parts.push(BodyPart::reference("application/pdf".to_owned(),
+                                            Some("report.pdf".to_owned()),
+                                            reference));
+        let encoded = unwrap_result!(body.encode());
+        assert_eq!(encoded[0], MULTIPART_VERSION);
+        assert_eq!(unwrap_result!(MultipartBody::decode(&encoded)), body);
+        assert_eq!(unwrap_result!(MultipartBody::decode(&unwrap_result!(MultipartBody::new()
+                                                                            .encode()))),
+                   MultipartBody::new());
+
+        // Bodies which are too large, or have invalid content types or names, aren't encoded.
+        let mut large = MultipartBody::new();
+        large.parts.push(BodyPart::binary("application/octet-stream".to_owned(),
+                                          None,
+                                          vec![0; MAX_ENCRYPTED_BODY_SIZE - 10]));
+        assert!(large.encode().is_ok());
+        match large.encode_within(MAX_ENCRYPTED_BODY_SIZE) {
+            Err(Error::BodyTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        large.parts[0].content = PartContent::Binary(vec![0; MAX_BODY_SIZE]);
+        match large.encode() {
+            Err(Error::BodyTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        for content_type in &["", "text/plain\r\nX-Injected: 1", "text/plain; name=\u{e9}"] {
+            let mut invalid = body.clone();
+            invalid.parts[0].content_type = content_type.to_string();
+            match invalid.encode() {
+                Err(Error::Malformed) => (),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+        for name in &["../../.bashrc", "C:\\Windows\\evil.exe", "a\u{0}b", "report\n.pdf"] {
+            let mut invalid = body.clone();
+            invalid.parts[2].name = Some(name.to_string());
+            match invalid.encode() {
+                Err(Error::Malformed) => (),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+        let mut unicode = body.clone();
+        unicode.parts[2].name = Some("r\u{e9}sum\u{e9}.pdf".to_owned());
+        assert_eq!(unwrap_result!(MultipartBody::decode(&unwrap_result!(unicode.encode()))),
+                   unicode);
+
+        // Malformed input is rejected, including every truncation of a valid body.
+        for length in 0..encoded.len() {
+            assert!(MultipartBody::decode(&encoded[..length]).is_err());
+        }
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(MultipartBody::decode(&trailing).is_err());
+        let mut wrong_version = encoded.clone();
+        wrong_version[0] = MULTIPART_VERSION + 1;
+        assert!(MultipartBody::decode(&wrong_version).is_err());
+        let mut huge_count = vec![MULTIPART_VERSION];
+        huge_count.extend_from_slice(&[255; 8]);
+        assert!(MultipartBody::decode(&huge_count).is_err());
+        for _ in 0..100 {
+            let mut corrupted = encoded.clone();
+            let index = rand::random::<usize>() % corrupted.len();
+            corrupted[index] = rand::random();
+            let _ = MultipartBody::decode(&corrupted);
+        }
+        match MultipartBody::decode(&vec![MULTIPART_VERSION; MAX_BODY_SIZE + 1]) {
+            Err(Error::InputTooLarge) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
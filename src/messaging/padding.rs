@@ -0,0 +1,160 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use super::{Error, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE, MAX_HEADER_METADATA_SIZE};
+
+/// The smallest size class of a [padded](struct.MpidMessageBuilder.html#method.pad) message body
+/// (256 bytes).
+pub const MIN_BODY_SIZE_CLASS: usize = 256;
+/// The smallest size class of [padded](struct.MpidMessageBuilder.html#method.pad) header metadata
+/// (32 bytes).
+pub const MIN_METADATA_SIZE_CLASS: usize = 32;
+/// The space taken by the length prefix of padded data (4 bytes), which reduces the room left for
+/// a padded message's metadata and body.
+pub const PADDING_OVERHEAD: usize = LENGTH_SIZE;
+
+// Padded data is prefixed with its actual length as a little-endian u32.
+const LENGTH_SIZE: usize = 4;
+// Each size class is this many times larger than the one below it.
+const CLASS_GROWTH: usize = 4;
+
+// Padding rounds data up to one of the size classes between `min_class` and `limit`: `min_class`
+// multiplied by successive powers of `CLASS_GROWTH`, with `limit` as the largest class.
+#[derive(Clone, Copy)]
+pub struct SizeClasses {
+    pub min_class: usize,
+    pub limit: usize,
+}
+
+impl SizeClasses {
+    // The size classes of unencrypted header metadata.
+    pub fn metadata() -> SizeClasses {
+        SizeClasses {
+            min_class: MIN_METADATA_SIZE_CLASS,
+            limit: MAX_HEADER_METADATA_SIZE,
+        }
+    }
+
+    // The size classes of a message body, applied to the plaintext if the body is encrypted.
+    pub fn body(encrypted: bool) -> SizeClasses {
+        SizeClasses {
+            min_class: MIN_BODY_SIZE_CLASS,
+            limit: if encrypted {
+                MAX_ENCRYPTED_BODY_SIZE
+            } else {
+                MAX_BODY_SIZE
+            },
+        }
+    }
+
+    // The smallest class able to hold `length` bytes of padded data, if any.
+    fn class_of(&self, length: usize) -> Option<usize> {
+        if length > self.limit {
+            return None;
+        }
+        let mut class = self.min_class;
+        while class < length {
+            class = class.saturating_mul(CLASS_GROWTH);
+        }
+        Some(if class > self.limit { self.limit } else { class })
+    }
+
+    // Prefixes `data` with its length and pads it with zeros up to its size class.  Returns `None`
+    // if `data` doesn't fit within the largest class.
+    pub fn pad(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let class = match self.class_of(LENGTH_SIZE + data.len()) {
+            Some(class) => class,
+            None => return None,
+        };
+        let mut padded = Vec::with_capacity(class);
+        for i in 0..LENGTH_SIZE {
+            padded.push((data.len() >> (8 * i)) as u8);
+        }
+        padded.extend_from_slice(data);
+        padded.resize(class, 0);
+        Some(padded)
+    }
+
+    // Strips the padding added by `pad()`.  The padding must be exactly as `pad()` produces it, so
+    // that the padded form of any data is unique.
+    pub fn unpad(&self, padded: &[u8]) -> Result<Vec<u8>, Error> {
+        if padded.len() < LENGTH_SIZE {
+            return Err(Error::Malformed);
+        }
+        let length = padded[..LENGTH_SIZE]
+                         .iter()
+                         .rev()
+                         .fold(0, |length, &byte| (length << 8) | byte as usize);
+        let data = &padded[LENGTH_SIZE..];
+        if length > data.len() || self.class_of(LENGTH_SIZE + length) != Some(padded.len()) ||
+           data[length..].iter().any(|&byte| byte != 0) {
+            return Err(Error::Malformed);
+        }
+        Ok(data[..length].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use messaging::{self, MAX_BODY_SIZE, MAX_ENCRYPTED_BODY_SIZE};
+
+    #[test]
+    fn full() {
+        let classes = SizeClasses::body(false);
+        let expected = [(0, 256),
+                         (252, 256),
+                         (253, 1024),
+                         (1020, 1024),
+                         (1021, 4096),
+                         (65532, 65536),
+                         (65533, MAX_BODY_SIZE),
+                         (MAX_BODY_SIZE - PADDING_OVERHEAD, MAX_BODY_SIZE)];
+        for &(length, class) in &expected {
+            let data = messaging::generate_random_bytes(length);
+            let padded = unwrap_result!(classes.pad(&data).ok_or(()));
+            assert_eq!(padded.len(), class);
+            assert_eq!(unwrap_result!(classes.unpad(&padded)), data);
+        }
+        assert!(classes.pad(&vec![0; MAX_BODY_SIZE - PADDING_OVERHEAD + 1]).is_none());
+
+        let metadata_classes = SizeClasses::metadata();
+        assert_eq!(unwrap_result!(metadata_classes.pad(&[]).ok_or(())).len(), 32);
+        assert_eq!(unwrap_result!(metadata_classes.pad(&[0; 29]).ok_or(())).len(), 128);
+        assert!(metadata_classes.pad(&[0; 125]).is_none());
+        assert_eq!(unwrap_result!(SizeClasses::body(true).pad(&[0; 65533]).ok_or(())).len(),
+                   MAX_ENCRYPTED_BODY_SIZE);
+
+        // Only padding exactly as produced by `pad()` is accepted.
+        let data = messaging::generate_random_bytes(100);
+        let padded = unwrap_result!(classes.pad(&data).ok_or(()));
+        let mut overlong_length = padded.clone();
+        overlong_length[3] = 1;
+        let mut non_zero = padded.clone();
+        non_zero[255] = 1;
+        let mut oversized_class = padded.clone();
+        oversized_class.resize(1024, 0);
+        for malformed in &[vec![],
+                           vec![0; 3],
+                           padded[..255].to_vec(),
+                           overlong_length,
+                           non_zero,
+                           oversized_class] {
+            assert!(classes.unpad(malformed).is_err());
+        }
+    }
+}
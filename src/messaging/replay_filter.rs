@@ -0,0 +1,260 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::{HashMap, VecDeque};
+
+use super::{Error, GUID_SIZE, MpidHeader};
+use xor_name::XorName;
+
+type Key = (XorName, [u8; GUID_SIZE]);
+
+/// The result of checking a header against a [`ReplayFilter`](struct.ReplayFilter.html).
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Seen {
+    /// No header with the same sender and GUID has been recorded.
+    New,
+    /// An identical header has been recorded, e.g. where a put is retried or replayed.
+    Duplicate,
+    /// A different header with the same sender and GUID has been recorded.
+    Conflict,
+}
+
+/// Remembers the `(sender, guid)` pairs of recently seen headers, so that replays can be dropped
+/// and retried puts handled idempotently.
+///
+/// Memory is bounded in three ways: entries are forgotten once they are older than the filter's
+/// window, beyond the filter's per-sender capacity the oldest entries of the same sender are
+/// forgotten early, and beyond its total capacity the oldest entries of any sender are forgotten
+/// early.  As the per-sender capacity is the lower of the two, no single sender can cause the
+/// entries of another to be forgotten.  A replay arriving after its entry has been forgotten is
+/// reported as `Seen::New`; the window should be at least as long as the maximum age plus the
+/// maximum future skew allowed by the [`ClockSkewPolicy`](struct.ClockSkewPolicy.html) applied to
+/// the same headers, so that such replays are rejected by the policy instead.
+#[derive(Clone, Debug)]
+pub struct ReplayFilter {
+    window: u64,
+    capacity: usize,
+    total_capacity: usize,
+    // The name of the header recorded for each key, with the time at which it was recorded.
+    seen: HashMap<Key, (XorName, u64)>,
+    // The GUIDs recorded for each sender, in the order in which they were recorded.
+    senders: HashMap<XorName, VecDeque<(u64, [u8; GUID_SIZE])>>,
+    // Keys in the order in which they were recorded, including any since forgotten early.  It's
+    // compacted once most of its keys have been forgotten, so it holds at most twice as many keys
+    // as `seen`.
+    order: VecDeque<(u64, Key)>,
+}
+
+impl ReplayFilter {
+    /// Constructs a filter remembering headers for `window` seconds, and at most `capacity` of
+    /// each sender's headers and `total_capacity` headers overall at a time.
+    pub fn new(window: u64, capacity: usize, total_capacity: usize) -> ReplayFilter {
+        ReplayFilter {
+            window: window,
+            capacity: capacity,
+            total_capacity: total_capacity,
+            seen: HashMap::new(),
+            senders: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Checks `header` against the headers recorded up to `now`, in seconds since the Unix epoch,
+    /// without recording it.
+    pub fn check(&self, header: &MpidHeader, now: u64) -> Result<Seen, Error> {
+        let key = (header.sender().clone(), *header.guid());
+        match self.seen.get(&key) {
+            Some(&(ref name, seen_at)) if !self.is_stale(seen_at, now) => {
+                if *name == try!(header.name()) {
+                    Ok(Seen::Duplicate)
+                } else {
+                    Ok(Seen::Conflict)
+                }
+            }
+            _ => Ok(Seen::New),
+        }
+    }
+
+    /// Checks `header` as per [`check()`](#method.check), then records it at `now` if it's new.
+    pub fn insert(&mut self, header: &MpidHeader, now: u64) -> Result<Seen, Error> {
+        self.remove_stale(now);
+        let seen = try!(self.check(header, now));
+        if seen == Seen::New && self.capacity > 0 && self.total_capacity > 0 {
+            let name = try!(header.name());
+            let sender = header.sender().clone();
+            {
+                let guids = self.senders.entry(sender.clone()).or_insert_with(VecDeque::new);
+                if guids.len() >= self.capacity {
+                    if let Some((_, oldest)) = guids.pop_front() {
+                        let _ = self.seen.remove(&(sender.clone(), oldest));
+                    }
+                }
+                guids.push_back((now, *header.guid()));
+            }
+            while self.seen.len() >= self.total_capacity {
+                if !self.forget_oldest() {
+                    break;
+                }
+            }
+            let key = (sender, *header.guid());
+            let _ = self.seen.insert(key.clone(), (name, now));
+            self.order.push_back((now, key));
+            if self.order.len() > 2 * self.seen.len() {
+                let seen = &self.seen;
+                self.order.retain(|&(seen_at, ref key)| {
+                    seen.get(key).map_or(false, |&(_, recorded_at)| recorded_at == seen_at)
+                });
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Sets the number of seconds for which headers are remembered.
+    pub fn set_window(&mut self, window: u64) {
+        self.window = window;
+    }
+
+    /// The number of headers currently remembered.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns whether no headers are currently remembered.
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    fn is_stale(&self, seen_at: u64, now: u64) -> bool {
+        now >= seen_at.saturating_add(self.window)
+    }
+
+    // Forgets the entries recorded before the window, skipping any already forgotten early.
+    fn remove_stale(&mut self, now: u64) {
+        while let Some(&(seen_at, _)) = self.order.front() {
+            if !self.is_stale(seen_at, now) {
+                break;
+            }
+            let _ = self.forget_oldest();
+        }
+    }
+
+    // Pops the oldest key from `order` and forgets its entry, unless it was already forgotten
+    // early.  Returns `false` if `order` is empty.
+    fn forget_oldest(&mut self) -> bool {
+        let (seen_at, (sender, guid)) = match self.order.pop_front() {
+            Some(oldest) => oldest,
+            None => return false,
+        };
+        let now_empty = match self.senders.get_mut(&sender) {
+            Some(guids) => {
+                if guids.front() == Some(&(seen_at, guid)) {
+                    let _ = guids.pop_front();
+                }
+                guids.is_empty()
+            }
+            None => false,
+        };
+        if now_empty {
+            let _ = self.senders.remove(&sender);
+        }
+        let key = (sender, guid);
+        if self.seen.get(&key).map_or(false, |&(_, recorded_at)| recorded_at == seen_at) {
+            let _ = self.seen.remove(&key);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use messaging::{GUID_SIZE, MpidHeader};
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let other_sender: XorName = rand::random();
+        let header_from = |sender: &XorName, guid: u8, metadata: Vec<u8>| {
+            unwrap_result!(MpidHeader::with_guid(sender.clone(),
+                                                 metadata,
+                                                 [guid; GUID_SIZE],
+                                                 0,
+                                                 &secret_key))
+        };
+        let header = |guid: u8, metadata: Vec<u8>| header_from(&sender, guid, metadata);
+
+        // Identical headers are duplicates, while reuse of a GUID for another header conflicts.
+        let mut filter = ReplayFilter::new(100, 2, 3);
+        assert!(filter.is_empty());
+        assert_eq!(unwrap_result!(filter.check(&header(0, vec![]), 0)), Seen::New);
+        assert!(filter.is_empty());
+        assert_eq!(unwrap_result!(filter.insert(&header(0, vec![]), 0)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&header(0, vec![]), 10)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.insert(&header(0, vec![1]), 10)), Seen::Conflict);
+        assert_eq!(filter.len(), 1);
+
+        // Entries are forgotten once older than the window.
+        assert_eq!(unwrap_result!(filter.check(&header(0, vec![]), 99)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.check(&header(0, vec![]), 100)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&header(1, vec![]), 100)), Seen::New);
+        assert_eq!(filter.len(), 1);
+
+        // Beyond the capacity, the sender's oldest entries are forgotten, while other senders'
+        // entries are kept.
+        let other = header_from(&other_sender, 1, vec![]);
+        assert_eq!(unwrap_result!(filter.insert(&other, 100)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&header(2, vec![]), 101)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&header(3, vec![]), 102)), Seen::New);
+        assert_eq!(filter.len(), 3);
+        assert_eq!(unwrap_result!(filter.check(&header(1, vec![]), 102)), Seen::New);
+        assert_eq!(unwrap_result!(filter.check(&header(2, vec![]), 102)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.check(&header(3, vec![]), 102)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.check(&other, 102)), Seen::Duplicate);
+
+        // Entries forgotten early and recorded again are remembered for a full window.
+        assert_eq!(unwrap_result!(filter.insert(&header(1, vec![]), 150)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&header(4, vec![]), 201)), Seen::New);
+        assert_eq!(unwrap_result!(filter.check(&header(1, vec![]), 201)), Seen::Duplicate);
+        assert_eq!(filter.len(), 2);
+        assert_eq!(unwrap_result!(filter.insert(&header(5, vec![]), 250)), Seen::New);
+        assert_eq!(filter.len(), 2);
+
+        // Beyond the total capacity, the oldest entries of any sender are forgotten.
+        let third_sender: XorName = rand::random();
+        let third = header_from(&third_sender, 1, vec![]);
+        assert_eq!(unwrap_result!(filter.insert(&other, 260)), Seen::New);
+        assert_eq!(unwrap_result!(filter.insert(&third, 270)), Seen::New);
+        assert_eq!(filter.len(), 3);
+        assert_eq!(unwrap_result!(filter.check(&header(4, vec![]), 270)), Seen::New);
+        assert_eq!(unwrap_result!(filter.check(&header(5, vec![]), 270)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.check(&other, 270)), Seen::Duplicate);
+        assert_eq!(unwrap_result!(filter.check(&third, 270)), Seen::Duplicate);
+
+        // A single sender can't grow the filter beyond its capacity, however many headers it
+        // sends within the window.
+        let mut filter = ReplayFilter::new(100, 2, 10);
+        for guid in 0..200 {
+            assert_eq!(unwrap_result!(filter.insert(&header(guid, vec![]), 0)), Seen::New);
+            assert_eq!(filter.len(), ::std::cmp::min(guid as usize + 1, 2));
+            assert!(filter.order.len() <= 4);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
+use super::Error;
+
+/// Version of the MPID messaging protocol, included in every payload signed by this module.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The contexts in which MPID messaging signatures are made.  The domain's tag and the
+/// [`PROTOCOL_VERSION`](constant.PROTOCOL_VERSION.html) prefix every signed payload, so a
+/// signature made in one context (or by another protocol using the same key) is never valid in
+/// another.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Domain {
+    /// Signatures of [`MpidHeader`](struct.MpidHeader.html)s.
+    Header,
+    /// Signatures of the recipient and body of [`MpidMessage`](struct.MpidMessage.html)s.
+    Message,
+}
+
+impl Domain {
+    /// The tag identifying this domain.
+    pub fn tag(&self) -> &'static [u8] {
+        match *self {
+            Domain::Header => b"safe_network_common::messaging::MpidHeader",
+            Domain::Message => b"safe_network_common::messaging::MpidMessage",
+        }
+    }
+}
+
+/// Returns the bytes which are actually signed for `payload` in `domain`: the length of the
+/// domain's tag, the tag, the little-endian protocol version, then `payload`.
+pub fn signed_data(domain: Domain, payload: &[u8]) -> Vec<u8> {
+    let tag = domain.tag();
+    let mut data = Vec::with_capacity(1 + tag.len() + 2 + payload.len());
+    data.push(tag.len() as u8);
+    data.extend_from_slice(tag);
+    data.push(PROTOCOL_VERSION as u8);
+    data.push((PROTOCOL_VERSION >> 8) as u8);
+    data.extend_from_slice(payload);
+    data
+}
+
+/// A source of Ed25519 signatures for MPID messaging, e.g. a key held in process memory, in a
+/// separate agent process or by a remote signing service.
+///
+/// Implementations are handed the complete [`signed_data()`](fn.signed_data.html) and needn't
+/// apply any domain separation themselves.  `SecretKey` is the default implementation.
+pub trait Signer {
+    /// Signs `data`, returning a detached signature.  Implementations which can fail, e.g. where
+    /// the signer is unreachable, should return `Error::SigningFailed`.
+    fn sign(&self, data: &[u8]) -> Result<Signature, Error>;
+}
+
+/// The counterpart of [`Signer`](trait.Signer.html), checking detached signatures over
+/// [`signed_data()`](fn.signed_data.html).  `PublicKey` is the default implementation.
+pub trait Verifier {
+    /// Returns whether `signature` is a valid signature of `data`.
+    fn verify(&self, signature: &Signature, data: &[u8]) -> bool;
+}
+
+impl Signer for SecretKey {
+    fn sign(&self, data: &[u8]) -> Result<Signature, Error> {
+        Ok(sign::sign_detached(data, self))
+    }
+}
+
+impl Verifier for PublicKey {
+    fn verify(&self, signature: &Signature, data: &[u8]) -> bool {
+        sign::verify_detached(signature, data, self)
+    }
+}
+
+// Signs `payload` under `domain`.
+pub fn sign_detached<S: Signer + ?Sized>(domain: Domain,
+                                         payload: &[u8],
+                                         signer: &S)
+                                         -> Result<Signature, Error> {
+    signer.sign(&signed_data(domain, payload))
+}
+
+// Verifies that `signature` was made over `payload` under `domain`.
+pub fn verify_detached<V: Verifier + ?Sized>(domain: Domain,
+                                             signature: &Signature,
+                                             payload: &[u8],
+                                             verifier: &V)
+                                             -> bool {
+    verifier.verify(signature, &signed_data(domain, payload))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::{sign_detached, verify_detached};
+    use messaging::{Error, GUID_SIZE, MpidHeader, MpidMessage};
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let payload = b"payload";
+        let signature = unwrap_result!(sign_detached(Domain::Header, payload, &secret_key));
+        assert!(verify_detached(Domain::Header, &signature, payload, &public_key));
+        assert!(!verify_detached(Domain::Message, &signature, payload, &public_key));
+
+        // Signatures over the raw payload, as made without domain separation, are rejected.
+        let raw_signature = sign::sign_detached(payload, &secret_key);
+        assert!(!verify_detached(Domain::Header, &raw_signature, payload, &public_key));
+        assert!(sign::verify_detached(&signature,
+                                      &signed_data(Domain::Header, payload),
+                                      &public_key));
+    }
+
+    // A signer standing in for one held outside the process, which may be unavailable.
+    struct RemoteSigner {
+        secret_key: SecretKey,
+        available: bool,
+    }
+
+    impl Signer for RemoteSigner {
+        fn sign(&self, data: &[u8]) -> Result<Signature, Error> {
+            if self.available {
+                Ok(sign::sign_detached(data, &self.secret_key))
+            } else {
+                Err(Error::SigningFailed)
+            }
+        }
+    }
+
+    #[test]
+    fn custom_signer() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let mut signer = RemoteSigner {
+            secret_key: secret_key.clone(),
+            available: true,
+        };
+
+        // Messages are identical whether signed directly with the key or via the custom signer, and
+        // can be verified via a trait object.
+        let guid = [1u8; GUID_SIZE];
+        let message = unwrap_result!(MpidMessage::with_guid(sender,
+                                                            vec![],
+                                                            recipient,
+                                                            vec![2],
+                                                            guid,
+                                                            0,
+                                                            &signer));
+        let expected = unwrap_result!(MpidMessage::with_guid(sender,
+                                                             vec![],
+                                                             recipient,
+                                                             vec![2],
+                                                             guid,
+                                                             0,
+                                                             &secret_key));
+        assert_eq!(message, expected);
+        let verifier: &Verifier = &public_key;
+        assert!(message.verify(verifier));
+
+        // Failures of the signer are propagated.
+        signer.available = false;
+        match MpidHeader::new(sender, vec![], &signer) {
+            Err(Error::SigningFailed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}